@@ -0,0 +1,588 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::{get, post, put},
+    Router,
+};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthUser, UserId};
+use crate::ice::IceServers;
+use crate::keys::{self, DeviceSummary, KeyBundle, KeyUpload, PrekeyCount, PrekeyUpload};
+use crate::presence::{self, Presence};
+use crate::ratelimit::FrameKind;
+use crate::registry::QueueStats;
+use crate::store::{
+    CallQuery, CallRecord, CallStatus, ChatMessage, Conference, Conversation, Cursor, DeviceKeys,
+    MessageQuery, ReadReceipt,
+};
+use crate::ws::{self, AppState};
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub target_id: Option<String>,
+    pub group_id: Option<String>,
+    pub limit: Option<i64>,
+    /// Only messages older than this cursor (scrolling back).
+    pub before: Option<Cursor>,
+    /// Only messages newer than this cursor (catching up).
+    pub after: Option<Cursor>,
+}
+
+#[derive(Deserialize)]
+pub struct CallHistoryQuery {
+    /// Comma-separated statuses, e.g. `missed,declined`; all if absent.
+    pub status: Option<String>,
+    /// Only calls started at or after this time (ms since the epoch).
+    pub since: Option<i64>,
+    /// Only calls started before this time (ms since the epoch).
+    pub until: Option<i64>,
+    /// Only calls older than this cursor (the next page).
+    pub before: Option<Cursor>,
+    pub limit: Option<i64>,
+}
+
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_PRESENCE_LOOKUP: usize = 200;
+
+#[derive(Deserialize)]
+pub struct PresenceQuery {
+    /// Comma-separated user ids.
+    pub user_ids: String,
+}
+
+#[derive(Serialize)]
+pub struct MessagePage {
+    /// Chronological order, oldest first.
+    pub messages: Vec<ChatMessage>,
+    /// Pass back as `after` when the query used `after`, otherwise as `before`.
+    /// `None` once there is nothing further in that direction.
+    pub next_cursor: Option<Cursor>,
+    /// Messages from others after the caller's read cursor, across the whole conversation.
+    pub unread_count: i64,
+    /// How far every other participant has read, for "seen by".
+    pub read_by: Vec<ReadReceipt>,
+}
+
+#[derive(Serialize)]
+pub struct ThreadPage {
+    pub root: ChatMessage,
+    /// Oldest first.
+    pub replies: Vec<ChatMessage>,
+    /// Pass back as `after` for the next page; `None` on the last one.
+    pub next_cursor: Option<Cursor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// A call as one of its parties sees it in their history. Signaling never
+/// reaches the history; only the call's lifecycle does.
+#[derive(Serialize)]
+pub struct CallEntry {
+    pub call_id: String,
+    /// The other party.
+    pub counterpart_id: String,
+    pub direction: CallDirection,
+    pub video: bool,
+    pub status: CallStatus,
+    pub started_at: i64,
+    pub answered_at: Option<i64>,
+    pub connected_at: Option<i64>,
+    pub ended_at: Option<i64>,
+    pub ended_by: Option<String>,
+    /// Time spent connected; `None` unless the call connected and is over.
+    pub duration_ms: Option<i64>,
+}
+
+impl CallEntry {
+    pub fn of(call: CallRecord, user_id: &str) -> Self {
+        let direction = if call.caller_id == user_id {
+            CallDirection::Outgoing
+        } else {
+            CallDirection::Incoming
+        };
+        CallEntry {
+            counterpart_id: call.peer_of(user_id).to_string(),
+            direction,
+            video: call.video,
+            status: call.status,
+            started_at: call.started_at,
+            answered_at: call.answered_at,
+            connected_at: call.connected_at,
+            ended_at: call.ended_at,
+            duration_ms: call.duration_ms(),
+            ended_by: call.ended_by,
+            call_id: call.call_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CallPage {
+    /// Newest first.
+    pub calls: Vec<CallEntry>,
+    /// Pass back as `before` for the next page; `None` on the last one.
+    pub next_cursor: Option<Cursor>,
+    /// Calls the caller missed since they last marked them seen, for a badge.
+    /// Counts the whole history, whatever the filters.
+    pub missed_count: i64,
+}
+
+/// Outbound queue health of this node.
+#[derive(Serialize)]
+pub struct QueueReport {
+    pub capacity: usize,
+    pub sessions: usize,
+    pub total_depth: usize,
+    pub max_depth: usize,
+    /// Ephemeral frames dropped across all sessions.
+    pub dropped: u64,
+    /// The caller's own sessions on this node; other users' are not listed.
+    pub mine: Vec<QueueStats>,
+}
+
+fn internal_error(e: impl std::fmt::Display) -> StatusCode {
+    eprintln!("Store error: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(|| async { "Realtime Hub is running!" }))
+        .route("/ws", get(ws::ws_handler))
+        .route("/messages", get(get_messages))
+        .route("/threads/:root_id", get(get_thread))
+        .route("/rooms", get(get_rooms))
+        .route("/calls", get(get_calls))
+        .route("/calls/seen", post(mark_calls_seen))
+        .route("/calls/:call_id", get(get_call))
+        .route("/conferences", get(get_conferences))
+        .route("/ice-servers", get(get_ice_servers))
+        .route("/devices", get(get_devices))
+        .route("/devices/:device_id", put(put_device).delete(delete_device))
+        .route("/devices/:device_id/prekeys", post(add_prekeys))
+        .route("/keys/:user_id", get(get_key_bundle))
+        .route("/presence", get(get_presence))
+        .route("/diagnostics/queues", get(get_queue_report))
+        .with_state(state)
+}
+
+async fn get_messages(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<MessagePage>, StatusCode> {
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+
+    // The caller always comes from the token; a conversation must be named.
+    let conversation = if let Some(group_id) = params.group_id {
+        if !state.can_read_group(&group_id, &user_id).await {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Conversation::Group(group_id)
+    } else if let Some(peer_id) = params.target_id {
+        Conversation::Direct {
+            user_id: user_id.to_string(),
+            peer_id,
+        }
+    } else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    let receipts = state
+        .messages
+        .read_receipts(&conversation)
+        .await
+        .map_err(internal_error)?;
+    let (mine, read_by): (Vec<ReadReceipt>, Vec<ReadReceipt>) =
+        receipts.into_iter().partition(|r| user_id == r.user_id);
+    let unread_count = state
+        .messages
+        .count_unread(
+            &conversation,
+            user_id.as_str(),
+            mine.first().map(ReadReceipt::cursor).as_ref(),
+        )
+        .await
+        .map_err(internal_error)?;
+
+    let forward = params.after.is_some();
+    let query = MessageQuery {
+        conversation,
+        before: params.before,
+        after: params.after,
+        // One extra row tells us whether another page exists.
+        limit: limit + 1,
+    };
+
+    let mut messages = state
+        .messages
+        .find_messages(&query)
+        .await
+        .map_err(internal_error)?;
+
+    let has_more = messages.len() as i64 > limit;
+    let next_cursor = if forward {
+        messages.truncate(limit as usize);
+        messages
+            .last()
+            .filter(|_| has_more)
+            .map(ChatMessage::cursor)
+    } else {
+        if has_more {
+            messages.remove(0);
+        }
+        messages
+            .first()
+            .filter(|_| has_more)
+            .map(ChatMessage::cursor)
+    };
+
+    Ok(Json(MessagePage {
+        messages,
+        next_cursor,
+        unread_count,
+        read_by,
+    }))
+}
+
+/// A thread's root and its replies. Open to whoever can read the root's
+/// conversation, and to the thread's participants.
+async fn get_thread(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Path(root_id): Path<String>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<ThreadPage>, StatusCode> {
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+
+    let root = state
+        .messages
+        .find_message(&root_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let allowed = if root.is_group {
+        let in_thread = user_id == root.sender_id
+            || state
+                .messages
+                .thread_participants(&root_id)
+                .await
+                .map_err(internal_error)?
+                .iter()
+                .any(|p| user_id == *p);
+        (in_thread || state.is_in_room(&root.target_id, &user_id).await)
+            && state.can_join_group(&root.target_id, &user_id).await
+    } else {
+        user_id == root.sender_id || user_id == root.target_id
+    };
+    if !allowed {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut replies = state
+        .messages
+        .find_replies(&root_id, params.after.as_ref(), limit + 1)
+        .await
+        .map_err(internal_error)?;
+    let has_more = replies.len() as i64 > limit;
+    replies.truncate(limit as usize);
+    let next_cursor = replies.last().filter(|_| has_more).map(ChatMessage::cursor);
+
+    Ok(Json(ThreadPage {
+        root,
+        replies,
+        next_cursor,
+    }))
+}
+
+/// Group ids of the rooms the caller joined.
+async fn get_rooms(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    let rooms = state
+        .rooms
+        .rooms_of(user_id.as_str())
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(rooms))
+}
+
+/// The caller's call history, newest first, optionally filtered by status
+/// and by when calls started.
+async fn get_calls(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Query(params): Query<CallHistoryQuery>,
+) -> Result<Json<CallPage>, StatusCode> {
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_PAGE_SIZE);
+    let statuses = match &params.status {
+        Some(raw) => raw
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<CallStatus>, _>>()
+            .map_err(|_| StatusCode::BAD_REQUEST)?,
+        None => Vec::new(),
+    };
+
+    let query = CallQuery {
+        user_id: user_id.to_string(),
+        statuses,
+        since: params.since,
+        until: params.until,
+        before: params.before,
+        // One extra row tells us whether another page exists.
+        limit: limit + 1,
+    };
+    let mut calls = state
+        .calls
+        .find_calls(&query)
+        .await
+        .map_err(internal_error)?;
+    let has_more = calls.len() as i64 > limit;
+    calls.truncate(limit as usize);
+    let next_cursor = calls.last().filter(|_| has_more).map(CallRecord::cursor);
+
+    let seen_at = state
+        .calls
+        .calls_seen_at(user_id.as_str())
+        .await
+        .map_err(internal_error)?;
+    let missed_count = state
+        .calls
+        .count_missed(user_id.as_str(), seen_at)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(CallPage {
+        calls: calls
+            .into_iter()
+            .map(|call| CallEntry::of(call, user_id.as_str()))
+            .collect(),
+        next_cursor,
+        missed_count,
+    }))
+}
+
+/// One of the caller's calls. Other people's calls read as not found.
+async fn get_call(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Path(call_id): Path<String>,
+) -> Result<Json<CallEntry>, StatusCode> {
+    let call = state
+        .calls
+        .find_call(&call_id)
+        .await
+        .map_err(internal_error)?
+        .filter(|call| call.involves(user_id.as_str()))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(CallEntry::of(call, user_id.as_str())))
+}
+
+/// Clears the missed-call badge: calls missed until now stop counting.
+async fn mark_calls_seen(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+) -> Result<StatusCode, StatusCode> {
+    state
+        .calls
+        .mark_calls_seen(user_id.as_str(), ws::now_millis())
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Group calls the caller took part in, newest first, with everyone who joined.
+async fn get_conferences(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<Vec<Conference>>, StatusCode> {
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_PAGE_SIZE);
+    let conferences = state
+        .calls
+        .recent_conferences(user_id.as_str(), limit)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(conferences))
+}
+
+/// STUN and TURN servers for the caller's next call. TURN credentials are
+/// short-lived; fetch fresh ones before every call.
+async fn get_ice_servers(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+) -> Json<IceServers> {
+    let now = (ws::now_millis() / 1000) as u64;
+    Json(state.ice.servers_for(&user_id, now))
+}
+
+/// The caller's devices in the key directory.
+async fn get_devices(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DeviceSummary>>, StatusCode> {
+    let devices = state
+        .keys
+        .device_keys(user_id.as_str())
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(devices.iter().map(DeviceSummary::from).collect()))
+}
+
+/// Publishes a device's public keys, replacing what it published before.
+async fn put_device(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(upload): Json<KeyUpload>,
+) -> Result<Json<DeviceSummary>, StatusCode> {
+    if !keys::valid_device_id(&device_id) || upload.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let devices = state
+        .keys
+        .device_keys(user_id.as_str())
+        .await
+        .map_err(internal_error)?;
+    let known = devices.iter().any(|d| d.device_id == device_id);
+    if !known && devices.len() >= keys::MAX_DEVICES {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let device = DeviceKeys {
+        user_id: user_id.to_string(),
+        device_id,
+        identity_key: upload.identity_key,
+        signed_prekey: upload.signed_prekey,
+        one_time_prekeys: upload.one_time_prekeys,
+        updated_at: ws::now_millis(),
+    };
+    state
+        .keys
+        .put_device_keys(&device)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(DeviceSummary::from(&device)))
+}
+
+/// Tops up a device's one-time prekeys.
+async fn add_prekeys(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(upload): Json<PrekeyUpload>,
+) -> Result<Json<PrekeyCount>, StatusCode> {
+    if keys::validate_prekeys(&upload.one_time_prekeys).is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let devices = state
+        .keys
+        .device_keys(user_id.as_str())
+        .await
+        .map_err(internal_error)?;
+    let device = devices
+        .iter()
+        .find(|d| d.device_id == device_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if device.one_time_prekeys.len() + upload.one_time_prekeys.len() > keys::MAX_PREKEYS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let prekeys_left = state
+        .keys
+        .add_prekeys(user_id.as_str(), &device_id, &upload.one_time_prekeys)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(PrekeyCount { prekeys_left }))
+}
+
+/// Takes a device out of the directory, say after signing out on it.
+async fn delete_device(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let removed = state
+        .keys
+        .remove_device(user_id.as_str(), &device_id)
+        .await
+        .map_err(internal_error)?;
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Keys to start encrypted sessions with every device of a user. Each fetch
+/// uses up one one-time prekey per device, so fetches are throttled per
+/// requester and per target, shared by all requesters, to keep anyone from
+/// draining a user's prekeys.
+async fn get_key_bundle(
+    AuthUser(requester): AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> Result<Json<KeyBundle>, StatusCode> {
+    let buckets = [
+        (FrameKind::KeyBundle, requester.as_str()),
+        (FrameKind::PeerKeyBundle, user_id.as_str()),
+    ];
+    if state.limits.take(&buckets).await.is_some() {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    let bundle = keys::bundle_for(&state, &user_id)
+        .await
+        .map_err(internal_error)?;
+    if bundle.devices.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(bundle))
+}
+
+async fn get_presence(
+    AuthUser(_): AuthUser,
+    State(state): State<AppState>,
+    Query(params): Query<PresenceQuery>,
+) -> Result<Json<Vec<Presence>>, StatusCode> {
+    let user_ids: Vec<&str> = params
+        .user_ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .collect();
+    if user_ids.len() > MAX_PRESENCE_LOOKUP {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let user_ids: Vec<UserId> = user_ids.into_iter().map(UserId::from).collect();
+    let presences = join_all(user_ids.iter().map(|id| presence::presence_of(&state, id))).await;
+    Ok(Json(presences))
+}
+
+async fn get_queue_report(
+    AuthUser(user_id): AuthUser,
+    State(state): State<AppState>,
+) -> Json<QueueReport> {
+    let all = state.connections.all_queue_stats();
+    Json(QueueReport {
+        capacity: state.connections.queue_capacity(),
+        sessions: all.len(),
+        total_depth: all.iter().map(|s| s.queue_depth).sum(),
+        max_depth: all.iter().map(|s| s.queue_depth).max().unwrap_or(0),
+        dropped: all.iter().map(|s| s.dropped).sum(),
+        mine: state.connections.queue_stats(user_id.as_str()),
+    })
+}
@@ -0,0 +1,133 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+
+/// Internal identity of an authenticated user.
+///
+/// The Auth service signs `userId` as an integer, older tokens carried a
+/// string `user_id`. Both are normalized to the same string form here so the
+/// rest of the hub only deals with one type.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct UserId(String);
+
+impl UserId {
+    pub fn new(id: impl Into<String>) -> Self {
+        UserId(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Borrow<str> for UserId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for UserId {
+    fn from(id: String) -> Self {
+        UserId(id)
+    }
+}
+
+impl From<&str> for UserId {
+    fn from(id: &str) -> Self {
+        UserId(id.to_string())
+    }
+}
+
+impl From<i64> for UserId {
+    fn from(id: i64) -> Self {
+        UserId(id.to_string())
+    }
+}
+
+impl PartialEq<str> for UserId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<String> for UserId {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawId {
+            Int(i64),
+            Str(String),
+        }
+
+        match RawId::deserialize(deserializer)? {
+            RawId::Int(id) => Ok(UserId::from(id)),
+            RawId::Str(id) if !id.is_empty() => Ok(UserId(id)),
+            RawId::Str(_) => Err(serde::de::Error::custom("user id must not be empty")),
+        }
+    }
+}
+
+/// Access token payload as signed by the Auth service:
+/// `{ "userId": 42, "email": "...", "exp": ... }`.
+/// Legacy tokens using `user_id` are still accepted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    #[serde(rename = "userId", alias = "user_id")]
+    pub user_id: UserId,
+    pub email: String,
+    pub exp: usize,
+}
+
+pub fn decode_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map(|data| data.claims)
+}
+
+/// Decodes a token with the hub's `JWT_SECRET`. Shared by the WebSocket
+/// upgrade (token in the query string) and the REST routes (bearer header).
+pub fn authenticate(token: &str) -> Result<Claims, StatusCode> {
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    decode_token(token, &secret).map_err(|_| StatusCode::UNAUTHORIZED)
+}
+
+/// The caller of a REST route, taken from `Authorization: Bearer <token>`.
+pub struct AuthUser(pub UserId);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        Ok(AuthUser(authenticate(token.trim())?.user_id))
+    }
+}
@@ -0,0 +1,34 @@
+//! Writes the WebSocket protocol as JSON Schema and TypeScript for the clients:
+//! `client_message.schema.json`, `server_message.schema.json` and `protocol.ts`.
+//!
+//! Usage: `cargo run --bin export_protocol [OUT_DIR]` (default `./protocol`).
+
+use realtime_hub::protocol;
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    let out_dir = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "protocol".to_string()),
+    );
+    std::fs::create_dir_all(&out_dir)?;
+
+    let files = [
+        (
+            "client_message.schema.json",
+            serde_json::to_string_pretty(&protocol::client_schema())?,
+        ),
+        (
+            "server_message.schema.json",
+            serde_json::to_string_pretty(&protocol::server_schema())?,
+        ),
+        ("protocol.ts", protocol::typescript()),
+    ];
+    for (name, contents) in files {
+        let path = out_dir.join(name);
+        std::fs::write(&path, contents)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
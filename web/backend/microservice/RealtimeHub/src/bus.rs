@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub type BusResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Redis channel every hub node publishes to and listens on.
+pub const REDIS_CHANNEL: &str = "realtime_hub:deliveries";
+
+/// Envelopes buffered per subscriber before a slow listener starts losing them.
+const BUS_CAPACITY: usize = 4096;
+
+/// Who a published frame is for. Each node resolves the route against its own
+/// sockets: a user's sessions, the local members of a group, or whoever
+/// locally watches a user's presence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Route {
+    User {
+        user_id: String,
+    },
+    Group {
+        group_id: String,
+        /// Member who should not get the frame, typically its author.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        except: Option<String>,
+    },
+    /// Each of the users, except where they are in the group's room and get
+    /// its traffic anyway.
+    OutsideGroup {
+        group_id: String,
+        user_ids: Vec<String>,
+    },
+    /// The user's presence subscribers, and the members of the groups the
+    /// user is in.
+    Watchers {
+        user_id: String,
+        group_ids: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Node that published it; that node has already delivered locally.
+    pub origin: String,
+    pub route: Route,
+    /// Serialized frame, sent to sockets as-is.
+    pub frame: String,
+    /// Fine to drop for a socket that is falling behind, like `typing`.
+    #[serde(default)]
+    pub ephemeral: bool,
+}
+
+/// Cross-node fan-out. Every node publishes what it delivers and replays what
+/// other nodes publish to its own sockets.
+#[async_trait]
+pub trait MessageBus: Send + Sync {
+    async fn publish(&self, envelope: &Envelope) -> BusResult<()>;
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope>;
+}
+
+/// In-process bus. Hub states sharing one instance behave like nodes sharing
+/// Redis, which is how the multi-node tests run. A single node with its own
+/// instance simply never hears from anyone else.
+pub struct LocalBus {
+    tx: broadcast::Sender<Envelope>,
+}
+
+impl LocalBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        LocalBus { tx }
+    }
+}
+
+impl Default for LocalBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl MessageBus for LocalBus {
+    async fn publish(&self, envelope: &Envelope) -> BusResult<()> {
+        // No subscribers just means no other node is listening.
+        let _ = self.tx.send(envelope.clone());
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.tx.subscribe()
+    }
+}
+
+/// Redis pub/sub bus shared by all hub instances behind the gateway.
+pub struct RedisBus {
+    publisher: redis::aio::ConnectionManager,
+    tx: broadcast::Sender<Envelope>,
+}
+
+impl RedisBus {
+    pub async fn connect(url: &str) -> BusResult<Self> {
+        let client = redis::Client::open(url)?;
+        let publisher = redis::aio::ConnectionManager::new(client.clone()).await?;
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+
+        // Fail fast on a bad URL, then keep the subscription alive in the background.
+        let pubsub = subscribe(&client).await?;
+        tokio::spawn(listen(client, pubsub, tx.clone()));
+
+        Ok(RedisBus { publisher, tx })
+    }
+}
+
+async fn subscribe(client: &redis::Client) -> redis::RedisResult<redis::aio::PubSub> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(REDIS_CHANNEL).await?;
+    Ok(pubsub)
+}
+
+async fn listen(
+    client: redis::Client,
+    mut pubsub: redis::aio::PubSub,
+    tx: broadcast::Sender<Envelope>,
+) {
+    loop {
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let payload: String = match msg.get_payload() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    eprintln!("Bad payload on {}: {}", REDIS_CHANNEL, e);
+                    continue;
+                }
+            };
+            match serde_json::from_str::<Envelope>(&payload) {
+                Ok(envelope) => {
+                    let _ = tx.send(envelope);
+                }
+                Err(e) => eprintln!("Bad envelope on {}: {}", REDIS_CHANNEL, e),
+            }
+        }
+        drop(messages);
+
+        eprintln!("Lost Redis subscription, reconnecting");
+        pubsub = loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            match subscribe(&client).await {
+                Ok(pubsub) => break pubsub,
+                Err(e) => eprintln!("Redis resubscribe failed: {}", e),
+            }
+        };
+    }
+}
+
+#[async_trait]
+impl MessageBus for RedisBus {
+    async fn publish(&self, envelope: &Envelope) -> BusResult<()> {
+        let payload = serde_json::to_string(envelope)?;
+        let mut conn = self.publisher.clone();
+        conn.publish::<_, _, ()>(REDIS_CHANNEL, payload).await?;
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.tx.subscribe()
+    }
+}
@@ -0,0 +1,422 @@
+use std::time::{Duration, Instant};
+
+use crate::auth::UserId;
+use crate::bus::Route;
+use crate::protocol::{ErrorCode, ServerMessage};
+use crate::registry::Outbound;
+use crate::store::{CallRecord, CallStatus};
+use crate::ws::{now_millis, AppState};
+
+pub const RING_TIMEOUT: Duration = Duration::from_secs(45);
+/// Longest an invite holds the parties' lines; a node dying mid-invite
+/// leaves them free again after this.
+const LINE_HOLD: Duration = Duration::from_secs(5);
+/// How long an invite waits for lines another invite holds.
+const LINE_WAIT: Duration = Duration::from_secs(2);
+const LINE_RETRY: Duration = Duration::from_millis(10);
+/// Every participant of a group call streams to every other one, so a mesh
+/// much larger than this overwhelms clients.
+pub const MAX_PARTICIPANTS: usize = 8;
+
+/// How long a call may ring before it counts as missed, and how many people
+/// fit into a group call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallConfig {
+    pub ring_timeout: Duration,
+    pub max_participants: usize,
+}
+
+impl CallConfig {
+    /// Reads `CALL_RING_TIMEOUT_SECS` and `CALL_MAX_PARTICIPANTS`, keeping the
+    /// default for anything unset or unparsable.
+    pub fn from_env() -> Self {
+        let ring_timeout = std::env::var("CALL_RING_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(RING_TIMEOUT, Duration::from_secs);
+        let max_participants = std::env::var("CALL_MAX_PARTICIPANTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max >= 2)
+            .unwrap_or(MAX_PARTICIPANTS);
+        CallConfig {
+            ring_timeout,
+            max_participants,
+        }
+    }
+}
+
+impl Default for CallConfig {
+    fn default() -> Self {
+        CallConfig {
+            ring_timeout: RING_TIMEOUT,
+            max_participants: MAX_PARTICIPANTS,
+        }
+    }
+}
+
+/// Shows the call's current state to every device of both parties.
+async fn announce(state: &AppState, call: &CallRecord) {
+    let frame = ServerMessage::CallState(call.clone()).to_frame();
+    for user_id in [&call.caller_id, &call.callee_id] {
+        let route = Route::User {
+            user_id: user_id.clone(),
+        };
+        state.deliver(route, frame.clone()).await;
+    }
+}
+
+/// Applies `change` to the call if nobody moved it on since it was read, and
+/// announces the result. Returns the updated call if it went through.
+async fn transition(
+    state: &AppState,
+    call: &CallRecord,
+    change: impl FnOnce(&mut CallRecord),
+) -> Option<CallRecord> {
+    let mut updated = call.clone();
+    change(&mut updated);
+    match state.calls.update_call(&updated, call.status).await {
+        Ok(true) => {
+            announce(state, &updated).await;
+            Some(updated)
+        }
+        Ok(false) => None,
+        Err(e) => {
+            eprintln!("Failed to save call {}: {}", call.call_id, e);
+            None
+        }
+    }
+}
+
+/// Moves the call into a final status.
+async fn finish(
+    state: &AppState,
+    call: &CallRecord,
+    status: CallStatus,
+    ended_by: Option<&UserId>,
+) -> Option<CallRecord> {
+    transition(state, call, |call| {
+        call.status = status;
+        call.ended_at = Some(now_millis());
+        call.ended_by = ended_by.map(UserId::to_string);
+    })
+    .await
+}
+
+/// Turns a call that rang unanswered for too long into a missed one.
+async fn time_out(state: &AppState, call: &CallRecord) -> Option<CallRecord> {
+    finish(state, call, CallStatus::Missed, None).await
+}
+
+/// The user's live calls. Calls left ringing past the timeout, say by a node
+/// that went away before its timer fired, are missed and left out.
+async fn live_calls(state: &AppState, user_id: &str) -> Option<Vec<CallRecord>> {
+    let calls = match state.calls.live_calls(user_id).await {
+        Ok(calls) => calls,
+        Err(e) => {
+            eprintln!("Failed to load calls of {}: {}", user_id, e);
+            return None;
+        }
+    };
+    let deadline = now_millis() - state.call_config.ring_timeout.as_millis() as i64;
+    let mut live = Vec::with_capacity(calls.len());
+    for call in calls {
+        if call.status == CallStatus::Ringing && call.started_at < deadline {
+            time_out(state, &call).await;
+        } else {
+            live.push(call);
+        }
+    }
+    Some(live)
+}
+
+/// Rings `callee_id` on behalf of `caller`. The caller learns the call id
+/// from the first `call_state`. A callee already in a call makes it `busy`;
+/// a callee who is ringing the caller right now is glare, and the caller is
+/// pointed at that call instead.
+pub async fn invite(
+    state: &AppState,
+    caller: &UserId,
+    tx: &Outbound,
+    callee_id: &str,
+    video: bool,
+) {
+    if caller.as_str() == callee_id {
+        let error = ServerMessage::error(ErrorCode::Invalid, "cannot call yourself", None);
+        tx.send(error.to_message());
+        return;
+    }
+    // Invites crossing on different nodes would otherwise both find the
+    // parties free and both ring.
+    let owner = uuid::Uuid::new_v4().to_string();
+    let mut parties = [caller.as_str(), callee_id];
+    parties.sort();
+    if !claim_lines(state, parties, &owner).await {
+        let text = format!("{} is being called right now, try again", callee_id);
+        tx.send(ServerMessage::error(ErrorCode::Invalid, text, None).to_message());
+        return;
+    }
+    let call = record_call(state, caller, tx, callee_id, video).await;
+    release_lines(state, parties, &owner).await;
+    let Some(call) = call else {
+        return;
+    };
+    announce(state, &call).await;
+
+    if call.status == CallStatus::Ringing {
+        let state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(state.call_config.ring_timeout).await;
+            time_out(&state, &call).await;
+        });
+    }
+}
+
+/// Checks both parties' calls and records the new one as ringing, or as
+/// `busy` if the callee is in a call. Call with both lines held.
+async fn record_call(
+    state: &AppState,
+    caller: &UserId,
+    tx: &Outbound,
+    callee_id: &str,
+    video: bool,
+) -> Option<CallRecord> {
+    let mine = live_calls(state, caller.as_str()).await?;
+    let incoming =
+        |call: &CallRecord| call.status == CallStatus::Ringing && *caller == call.callee_id;
+    if let Some(call) = mine
+        .iter()
+        .find(|call| incoming(call) && call.caller_id == callee_id)
+    {
+        let text = format!(
+            "{} is calling you already in call {}",
+            callee_id, call.call_id
+        );
+        tx.send(ServerMessage::error(ErrorCode::Glare, text, None).to_message());
+        tx.send(ServerMessage::CallState(call.clone()).to_message());
+        return None;
+    }
+    // Calls ringing for the caller do not stop them from calling out.
+    if let Some(call) = mine.iter().find(|call| !incoming(call)) {
+        let text = format!("already in call {}", call.call_id);
+        tx.send(ServerMessage::error(ErrorCode::Invalid, text, None).to_message());
+        return None;
+    }
+    let theirs = live_calls(state, callee_id).await?;
+
+    let now = now_millis();
+    let mut call = CallRecord {
+        call_id: uuid::Uuid::new_v4().to_string(),
+        caller_id: caller.to_string(),
+        callee_id: callee_id.to_string(),
+        video,
+        status: CallStatus::Ringing,
+        started_at: now,
+        answered_at: None,
+        connected_at: None,
+        ended_at: None,
+        ended_by: None,
+    };
+    if !theirs.is_empty() {
+        call.status = CallStatus::Busy;
+        call.ended_at = Some(now);
+    }
+    if let Err(e) = state.calls.insert_call(&call).await {
+        eprintln!("Failed to save call from {}: {}", caller, e);
+        return None;
+    }
+    Some(call)
+}
+
+/// Claims both parties' lines, waiting while another invite holds one.
+/// Returns `false` if they stayed taken for `LINE_WAIT`.
+async fn claim_lines(state: &AppState, parties: [&str; 2], owner: &str) -> bool {
+    let deadline = Instant::now() + LINE_WAIT;
+    loop {
+        let until = now_millis() + LINE_HOLD.as_millis() as i64;
+        let mut claimed = true;
+        for user_id in parties {
+            match state.calls.claim_line(user_id, owner, until).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    claimed = false;
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Failed to claim the line of {}: {}", user_id, e);
+                    release_lines(state, parties, owner).await;
+                    return false;
+                }
+            }
+        }
+        if claimed {
+            return true;
+        }
+        // Let go of a line we got, so the other invite can finish.
+        release_lines(state, parties, owner).await;
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(LINE_RETRY).await;
+    }
+}
+
+async fn release_lines(state: &AppState, parties: [&str; 2], owner: &str) {
+    for user_id in parties {
+        if let Err(e) = state.calls.release_line(user_id, owner).await {
+            eprintln!("Failed to release the line of {}: {}", user_id, e);
+        }
+    }
+}
+
+/// Relays an offer, answer or ICE candidate to the other party of one of the
+/// sender's live calls.
+pub async fn relay(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    target_id: &str,
+    payload: serde_json::Value,
+) {
+    let Some(calls) = live_calls(state, user_id.as_str()).await else {
+        return;
+    };
+    if !calls
+        .iter()
+        .any(|call| call.peer_of(user_id.as_str()) == target_id)
+    {
+        let text = format!("no live call with {}", target_id);
+        tx.send(ServerMessage::error(ErrorCode::NotFound, text, None).to_message());
+        return;
+    }
+    let frame = ServerMessage::Signal {
+        sender_id: user_id.to_string(),
+        payload,
+    }
+    .to_frame();
+    let route = Route::User {
+        user_id: target_id.to_string(),
+    };
+    state.deliver(route, frame).await;
+}
+
+/// Loads a call of the user's, or tells the socket there is none.
+async fn call_of(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    call_id: &str,
+) -> Option<CallRecord> {
+    match state.calls.find_call(call_id).await {
+        Ok(Some(call)) if call.involves(user_id.as_str()) => Some(call),
+        Ok(_) => {
+            let text = format!("no call {} of yours", call_id);
+            tx.send(ServerMessage::error(ErrorCode::NotFound, text, None).to_message());
+            None
+        }
+        Err(e) => {
+            eprintln!("Failed to load call {}: {}", call_id, e);
+            None
+        }
+    }
+}
+
+fn wrong_state(tx: &Outbound, call: &CallRecord) {
+    let text = format!("call {} is {}", call.call_id, call.status.as_str());
+    tx.send(ServerMessage::error(ErrorCode::Invalid, text, None).to_message());
+}
+
+/// The callee answers a ringing call. Their other devices stop ringing when
+/// they see it accepted.
+pub async fn accept(state: &AppState, user_id: &UserId, tx: &Outbound, call_id: &str) {
+    let Some(call) = call_of(state, user_id, tx, call_id).await else {
+        return;
+    };
+    if *user_id != call.callee_id || call.status != CallStatus::Ringing {
+        return wrong_state(tx, &call);
+    }
+    if transition(state, &call, |call| {
+        call.status = CallStatus::Accepted;
+        call.answered_at = Some(now_millis());
+    })
+    .await
+    .is_none()
+    {
+        reread(state, tx, call_id).await;
+    }
+}
+
+/// The callee turns a ringing call down.
+pub async fn decline(state: &AppState, user_id: &UserId, tx: &Outbound, call_id: &str) {
+    let Some(call) = call_of(state, user_id, tx, call_id).await else {
+        return;
+    };
+    if *user_id != call.callee_id || call.status != CallStatus::Ringing {
+        return wrong_state(tx, &call);
+    }
+    if finish(state, &call, CallStatus::Declined, Some(user_id))
+        .await
+        .is_none()
+    {
+        reread(state, tx, call_id).await;
+    }
+}
+
+/// Either party reports media flowing. Both usually do; the second report
+/// changes nothing.
+pub async fn connected(state: &AppState, user_id: &UserId, tx: &Outbound, call_id: &str) {
+    let Some(call) = call_of(state, user_id, tx, call_id).await else {
+        return;
+    };
+    match call.status {
+        CallStatus::Accepted => {
+            transition(state, &call, |call| {
+                call.status = CallStatus::Connected;
+                call.connected_at = Some(now_millis());
+            })
+            .await;
+        }
+        CallStatus::Connected => {}
+        _ => wrong_state(tx, &call),
+    }
+}
+
+/// Either party hangs up. Before an answer that is the caller giving up, or
+/// the callee declining. Hanging up a call that is over changes nothing.
+pub async fn end(state: &AppState, user_id: &UserId, tx: &Outbound, call_id: &str) {
+    let Some(call) = call_of(state, user_id, tx, call_id).await else {
+        return;
+    };
+    let status = match call.status {
+        CallStatus::Ringing if *user_id == call.callee_id => CallStatus::Declined,
+        CallStatus::Ringing => CallStatus::Missed,
+        CallStatus::Accepted | CallStatus::Connected => CallStatus::Ended,
+        _ => return,
+    };
+    if finish(state, &call, status, Some(user_id)).await.is_none() {
+        reread(state, tx, call_id).await;
+    }
+}
+
+/// After losing a race on the call, shows the socket what it turned into.
+async fn reread(state: &AppState, tx: &Outbound, call_id: &str) {
+    if let Ok(Some(call)) = state.calls.find_call(call_id).await {
+        tx.send(ServerMessage::CallState(call).to_message());
+    }
+}
+
+/// Hangs up every live call of a user whose last session on any node closed.
+/// Calls that were still ringing count as missed.
+pub async fn drop_calls(state: &AppState, user_id: &UserId) {
+    let Some(calls) = live_calls(state, user_id.as_str()).await else {
+        return;
+    };
+    for call in calls {
+        let status = if call.status == CallStatus::Ringing {
+            CallStatus::Missed
+        } else {
+            CallStatus::Ended
+        };
+        finish(state, &call, status, Some(user_id)).await;
+    }
+}
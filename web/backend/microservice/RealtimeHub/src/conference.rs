@@ -0,0 +1,150 @@
+use crate::auth::UserId;
+use crate::bus::Route;
+use crate::protocol::{ErrorCode, ServerMessage};
+use crate::registry::Outbound;
+use crate::store::{Conference, ConferenceJoin};
+use crate::ws::{now_millis, AppState};
+
+fn roster(conference: &Conference, joined: Option<&str>, left: Option<&str>) -> ServerMessage {
+    ServerMessage::ConferenceRoster {
+        call_id: conference.call_id.clone(),
+        group_id: conference.group_id.clone(),
+        participants: conference.active.clone(),
+        joined: joined.map(str::to_string),
+        left: left.map(str::to_string),
+        ended: conference.ended_at.is_some(),
+    }
+}
+
+fn on_roster(conference: &Conference, user_id: &str) -> bool {
+    conference.active.iter().any(|u| u == user_id)
+}
+
+/// Shows a roster change to the group's room.
+async fn broadcast_roster(
+    state: &AppState,
+    conference: &Conference,
+    joined: Option<&str>,
+    left: Option<&str>,
+) {
+    let route = Route::Group {
+        group_id: conference.group_id.clone(),
+        except: None,
+    };
+    let frame = roster(conference, joined, left).to_frame();
+    state.deliver(route, frame).await;
+}
+
+/// Puts the user into the group's call, starting one if none is running.
+/// Only members in the group's room may join, up to the configured number of
+/// participants. Newcomers send an offer to everyone already on the roster.
+pub async fn join(state: &AppState, user_id: &UserId, tx: &Outbound, group_id: &str) {
+    if !state.is_group_member(group_id, user_id)
+        || !state.can_post_to_group(group_id, user_id).await
+    {
+        let text = format!("join the room of group {} as a member first", group_id);
+        tx.send(ServerMessage::error(ErrorCode::Forbidden, text, None).to_message());
+        return;
+    }
+    let joined = state
+        .calls
+        .join_conference(
+            group_id,
+            user_id.as_str(),
+            now_millis(),
+            state.call_config.max_participants,
+        )
+        .await;
+    match joined {
+        Ok(ConferenceJoin::Joined(conference)) => {
+            broadcast_roster(state, &conference, Some(user_id.as_str()), None).await;
+        }
+        Ok(ConferenceJoin::AlreadyIn(conference)) => {
+            tx.send(roster(&conference, None, None).to_message());
+        }
+        Ok(ConferenceJoin::Full(conference)) => {
+            let text = format!(
+                "call {} has the maximum of {} participants",
+                conference.call_id, state.call_config.max_participants
+            );
+            tx.send(ServerMessage::error(ErrorCode::Full, text, None).to_message());
+        }
+        Err(e) => eprintln!(
+            "Failed to join {} to the call of {}: {}",
+            user_id, group_id, e
+        ),
+    }
+}
+
+/// Takes the user out of the group's call. The last one out ends it.
+pub async fn leave(state: &AppState, user_id: &UserId, group_id: &str) {
+    match state
+        .calls
+        .leave_conference(group_id, user_id.as_str(), now_millis())
+        .await
+    {
+        Ok(Some(conference)) => {
+            broadcast_roster(state, &conference, None, Some(user_id.as_str())).await;
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!(
+            "Failed to take {} out of the call of {}: {}",
+            user_id, group_id, e
+        ),
+    }
+}
+
+/// Relays an offer, answer or ICE candidate to one other participant of the
+/// group's call. Both ends must be on the roster.
+pub async fn relay(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    group_id: &str,
+    target_id: &str,
+    payload: serde_json::Value,
+) {
+    let conference = match state.calls.live_conference(group_id).await {
+        Ok(Some(conference))
+            if on_roster(&conference, user_id.as_str()) && on_roster(&conference, target_id) =>
+        {
+            conference
+        }
+        Ok(_) => {
+            let text = format!(
+                "{} and you are not both in the call of {}",
+                target_id, group_id
+            );
+            tx.send(ServerMessage::error(ErrorCode::NotFound, text, None).to_message());
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to load the call of {}: {}", group_id, e);
+            return;
+        }
+    };
+    let frame = ServerMessage::ConferenceSignal {
+        call_id: conference.call_id,
+        group_id: group_id.to_string(),
+        sender_id: user_id.to_string(),
+        payload,
+    };
+    let route = Route::User {
+        user_id: target_id.to_string(),
+    };
+    state.deliver(route, frame.to_frame()).await;
+}
+
+/// Takes a user whose last session on any node closed out of every call they are in.
+pub async fn leave_all(state: &AppState, user_id: &UserId) {
+    let conferences = match state.calls.conferences_of(user_id.as_str()).await {
+        Ok(conferences) => conferences,
+        Err(e) => {
+            eprintln!("Failed to load calls of {}: {}", user_id, e);
+            return;
+        }
+    };
+    for conference in conferences {
+        leave(state, user_id, &conference.group_id).await;
+    }
+}
@@ -1,5 +1,4 @@
-
-use mongodb::{Client, options::ClientOptions, Database};
+use mongodb::{options::ClientOptions, Client, Database};
 use std::env;
 use std::error::Error;
 
@@ -7,10 +6,23 @@ pub async fn connect_db() -> Result<Database, Box<dyn Error>> {
     let mongo_url = env::var("MONGODB_URL").expect("MONGODB_URL must be set");
     let client_options = ClientOptions::parse(mongo_url).await?;
     let client = Client::with_options(client_options)?;
-    
+
     // Check connection
     println!("Connected to MongoDB");
-    
+
     // Return database instance (using "realtime_hub" or derived from URL)
     Ok(client.database("realtime_hub"))
 }
+
+/// The Social service's database, which owns groups and their members.
+pub async fn connect_social_db() -> Result<Database, Box<dyn Error>> {
+    let mongo_url = env::var("SOCIAL_MONGODB_URL").expect("SOCIAL_MONGODB_URL must be set");
+    let client_options = ClientOptions::parse(mongo_url).await?;
+    let default_db = client_options
+        .default_database
+        .clone()
+        .unwrap_or_else(|| "social_db".to_string());
+    let client = Client::with_options(client_options)?;
+
+    Ok(client.database(&default_db))
+}
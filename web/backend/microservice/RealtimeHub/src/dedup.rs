@@ -0,0 +1,74 @@
+use dashmap::DashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth::UserId;
+
+/// How long a `(sender, client_msg_id)` pair is remembered.
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// What the server answered the first time a client message was accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptedMessage {
+    pub message_id: String,
+    pub timestamp: i64,
+}
+
+/// Remembers recently accepted client messages so a resend (after a dropped
+/// ack or a reconnect) is answered with the original ack instead of being
+/// stored and broadcast a second time. Only a fast path for resends to the
+/// same node: the message store refuses duplicates from any node.
+pub struct DedupCache {
+    window: Duration,
+    entries: DashMap<(UserId, String), (Instant, AcceptedMessage)>,
+    last_prune: Mutex<Instant>,
+}
+
+impl DedupCache {
+    pub fn new(window: Duration) -> Self {
+        DedupCache {
+            window,
+            entries: DashMap::new(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn get(&self, sender: &UserId, client_msg_id: &str) -> Option<AcceptedMessage> {
+        let key = (sender.clone(), client_msg_id.to_string());
+        let entry = self.entries.get(&key)?;
+        let (seen_at, accepted) = entry.value();
+        if seen_at.elapsed() < self.window {
+            Some(accepted.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, sender: &UserId, client_msg_id: &str, accepted: AcceptedMessage) {
+        self.entries.insert(
+            (sender.clone(), client_msg_id.to_string()),
+            (Instant::now(), accepted),
+        );
+        self.prune();
+    }
+
+    /// Drops expired entries, at most once per window.
+    fn prune(&self) {
+        let mut last_prune = self.last_prune.lock().unwrap();
+        if last_prune.elapsed() < self.window {
+            return;
+        }
+        *last_prune = Instant::now();
+        drop(last_prune);
+
+        let window = self.window;
+        self.entries
+            .retain(|_, (seen_at, _)| seen_at.elapsed() < window);
+    }
+}
+
+impl Default for DedupCache {
+    fn default() -> Self {
+        Self::new(DEDUP_WINDOW)
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(75);
+/// Shortest ping interval taken from the environment.
+pub const MIN_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the hub pings each socket, and how long a socket may stay
+/// silent (no frame of any kind, pongs included) before it is dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl HeartbeatConfig {
+    /// An interval of at least `MIN_HEARTBEAT_INTERVAL`, and a timeout longer
+    /// than it; a timeout that isn't would drop every client, so it becomes
+    /// twice the interval.
+    pub fn clamped(interval: Duration, timeout: Duration) -> Self {
+        let interval = interval.max(MIN_HEARTBEAT_INTERVAL);
+        let timeout = if timeout > interval {
+            timeout
+        } else {
+            interval * 2
+        };
+        HeartbeatConfig { interval, timeout }
+    }
+
+    /// Reads `HEARTBEAT_INTERVAL_SECS` and `HEARTBEAT_TIMEOUT_SECS`, keeping
+    /// the default for anything unset or unparsable, then clamps them.
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: Duration| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(default, Duration::from_secs)
+        };
+        HeartbeatConfig::clamped(
+            secs("HEARTBEAT_INTERVAL_SECS", HEARTBEAT_INTERVAL),
+            secs("HEARTBEAT_TIMEOUT_SECS", HEARTBEAT_TIMEOUT),
+        )
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: HEARTBEAT_INTERVAL,
+            timeout: HEARTBEAT_TIMEOUT,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha1::Sha1;
+use std::time::Duration;
+
+use crate::auth::UserId;
+
+/// How long issued TURN credentials stay valid. TURN servers check them on
+/// every allocation refresh, so this bounds how long a call can last too.
+pub const TURN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Bounds for `TURN_TTL_SECS`.
+pub const MIN_TURN_TTL: Duration = Duration::from_secs(60);
+pub const MAX_TURN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// ICE servers handed to clients. TURN servers get per-user credentials
+/// signed with the secret shared with the TURN server (coturn's
+/// `use-auth-secret` / `static-auth-secret`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IceConfig {
+    pub stun_urls: Vec<String>,
+    pub turn_urls: Vec<String>,
+    pub turn_secret: Option<String>,
+    pub ttl: Duration,
+}
+
+impl IceConfig {
+    /// Reads `STUN_URLS` and `TURN_URLS` (comma separated), `TURN_SECRET` and
+    /// `TURN_TTL_SECS`, clamped to `MIN_TURN_TTL..=MAX_TURN_TTL`. Without a
+    /// secret no TURN server is handed out.
+    pub fn from_env() -> Self {
+        let urls = |name: &str| {
+            std::env::var(name)
+                .map(|raw| {
+                    raw.split(',')
+                        .map(str::trim)
+                        .filter(|url| !url.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        IceConfig {
+            stun_urls: urls("STUN_URLS"),
+            turn_urls: urls("TURN_URLS"),
+            turn_secret: std::env::var("TURN_SECRET").ok().filter(|s| !s.is_empty()),
+            ttl: std::env::var("TURN_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(TURN_TTL, Duration::from_secs)
+                .clamp(MIN_TURN_TTL, MAX_TURN_TTL),
+        }
+    }
+
+    /// The servers for `user_id`, with TURN credentials valid from `now`
+    /// (unix seconds) for the configured TTL.
+    pub fn servers_for(&self, user_id: &UserId, now: u64) -> IceServers {
+        let mut ice_servers = Vec::new();
+        if !self.stun_urls.is_empty() {
+            ice_servers.push(IceServer {
+                urls: self.stun_urls.clone(),
+                username: None,
+                credential: None,
+            });
+        }
+        let expires_at = now.saturating_add(self.ttl.as_secs());
+        if let Some(secret) = self
+            .turn_secret
+            .as_deref()
+            .filter(|_| !self.turn_urls.is_empty())
+        {
+            let username = format!("{}:{}", expires_at, user_id);
+            ice_servers.push(IceServer {
+                urls: self.turn_urls.clone(),
+                credential: Some(turn_credential(secret, &username)),
+                username: Some(username),
+            });
+        }
+        IceServers {
+            ice_servers,
+            ttl: self.ttl.as_secs(),
+            expires_at,
+        }
+    }
+}
+
+/// One entry of `RTCConfiguration.iceServers`, ready to pass to the browser.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IceServers {
+    pub ice_servers: Vec<IceServer>,
+    /// Seconds the credentials are valid for.
+    pub ttl: u64,
+    /// Unix seconds; fetch new credentials before then.
+    pub expires_at: u64,
+}
+
+/// The TURN REST API password for `username`: base64 of its HMAC-SHA1 under
+/// the shared secret.
+pub fn turn_credential(secret: &str, username: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key");
+    mac.update(username.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,138 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::store::{DeviceKeys, Prekey, SignedPrekey, StoreResult};
+use crate::ws::AppState;
+
+/// Longest accepted device id, in bytes.
+pub const MAX_DEVICE_ID_LEN: usize = 64;
+/// Largest accepted public key, in bytes once decoded. X25519 keys are 32.
+pub const MAX_KEY_LEN: usize = 64;
+/// Largest accepted prekey signature, in bytes once decoded. Ed25519 ones are 64.
+pub const MAX_SIGNATURE_LEN: usize = 128;
+/// Most one-time prekeys a device may have waiting at once.
+pub const MAX_PREKEYS: usize = 100;
+/// Most devices per user in the directory.
+pub const MAX_DEVICES: usize = 10;
+
+/// What a device publishes when it registers or rotates its keys.
+#[derive(Debug, Deserialize)]
+pub struct KeyUpload {
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    #[serde(default)]
+    pub one_time_prekeys: Vec<Prekey>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrekeyUpload {
+    pub one_time_prekeys: Vec<Prekey>,
+}
+
+/// One of the caller's own devices, without its keys.
+#[derive(Debug, Serialize)]
+pub struct DeviceSummary {
+    pub device_id: String,
+    pub identity_key: String,
+    pub signed_prekey_id: u32,
+    /// Upload more once this runs low.
+    pub prekeys_left: usize,
+    pub updated_at: i64,
+}
+
+impl From<&DeviceKeys> for DeviceSummary {
+    fn from(keys: &DeviceKeys) -> Self {
+        DeviceSummary {
+            device_id: keys.device_id.clone(),
+            identity_key: keys.identity_key.clone(),
+            signed_prekey_id: keys.signed_prekey.key_id,
+            prekeys_left: keys.one_time_prekeys.len(),
+            updated_at: keys.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrekeyCount {
+    pub prekeys_left: usize,
+}
+
+/// What one device of a peer needs to start a session with it.
+#[derive(Debug, Serialize)]
+pub struct DeviceBundle {
+    pub device_id: String,
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// `None` once the device ran out; sessions can start without one.
+    pub one_time_prekey: Option<Prekey>,
+}
+
+/// A bundle for every device of a user. Encrypt once per device.
+#[derive(Debug, Serialize)]
+pub struct KeyBundle {
+    pub user_id: String,
+    pub devices: Vec<DeviceBundle>,
+}
+
+/// Whether `value` is base64 of at most `max_len` bytes.
+fn valid_base64(value: &str, max_len: usize) -> bool {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .is_ok_and(|bytes| !bytes.is_empty() && bytes.len() <= max_len)
+}
+
+pub fn valid_device_id(device_id: &str) -> bool {
+    !device_id.is_empty() && device_id.len() <= MAX_DEVICE_ID_LEN
+}
+
+/// Checks the keys are base64 of sensible sizes. Returns what is wrong, if anything.
+pub fn validate_prekeys(prekeys: &[Prekey]) -> Result<(), String> {
+    if prekeys.len() > MAX_PREKEYS {
+        return Err(format!(
+            "{} one-time prekeys, at most {} allowed",
+            prekeys.len(),
+            MAX_PREKEYS
+        ));
+    }
+    if let Some(prekey) = prekeys
+        .iter()
+        .find(|p| !valid_base64(&p.public_key, MAX_KEY_LEN))
+    {
+        return Err(format!("prekey {} is not a base64 key", prekey.key_id));
+    }
+    Ok(())
+}
+
+impl KeyUpload {
+    pub fn validate(&self) -> Result<(), String> {
+        if !valid_base64(&self.identity_key, MAX_KEY_LEN) {
+            return Err("identity_key is not a base64 key".to_string());
+        }
+        if !valid_base64(&self.signed_prekey.public_key, MAX_KEY_LEN) {
+            return Err("signed_prekey is not a base64 key".to_string());
+        }
+        if !valid_base64(&self.signed_prekey.signature, MAX_SIGNATURE_LEN) {
+            return Err("signed_prekey signature is not base64".to_string());
+        }
+        validate_prekeys(&self.one_time_prekeys)
+    }
+}
+
+/// Bundles for every device of `user_id`, each with a one-time prekey of its
+/// own taken out of the directory.
+pub async fn bundle_for(state: &AppState, user_id: &str) -> StoreResult<KeyBundle> {
+    let mut devices = Vec::new();
+    for keys in state.keys.device_keys(user_id).await? {
+        let one_time_prekey = state.keys.take_prekey(user_id, &keys.device_id).await?;
+        devices.push(DeviceBundle {
+            device_id: keys.device_id,
+            identity_key: keys.identity_key,
+            signed_prekey: keys.signed_prekey,
+            one_time_prekey,
+        });
+    }
+    Ok(KeyBundle {
+        user_id: user_id.to_string(),
+        devices,
+    })
+}
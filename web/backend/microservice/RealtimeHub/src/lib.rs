@@ -1,2 +1,18 @@
+pub mod api;
+pub mod auth;
+pub mod bus;
+pub mod calls;
+pub mod conference;
 pub mod db;
+pub mod dedup;
+pub mod heartbeat;
+pub mod ice;
+pub mod keys;
+pub mod presence;
+pub mod protocol;
+pub mod ratelimit;
+pub mod registry;
+pub mod sessions;
+pub mod store;
+pub mod typing;
 pub mod ws;
@@ -1,119 +1,95 @@
+use realtime_hub::bus::RedisBus;
+use realtime_hub::calls::CallConfig;
+use realtime_hub::heartbeat::HeartbeatConfig;
+use realtime_hub::ice::IceConfig;
+use realtime_hub::ratelimit::{RateLimitConfig, RedisBuckets};
+use realtime_hub::sessions::RedisSessions;
+use realtime_hub::store::{MongoStore, SocialGroups};
 use realtime_hub::ws::AppState;
-use realtime_hub::{db, ws};
+use realtime_hub::{api, db};
 
-use axum::{
-    extract::{Query, State},
-    response::Json,
-    routing::get,
-    Router,
-};
-use dashmap::DashMap;
 use dotenv::dotenv;
-use futures::stream::TryStreamExt;
-use mongodb::bson::doc;
-use serde::Deserialize;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-#[derive(Deserialize)]
-struct HistoryQuery {
-    user_id: String,
-    target_id: Option<String>,
-    group_id: Option<String>,
-    limit: Option<i64>,
-}
-
-async fn get_messages(
-    State(state): State<AppState>,
-    Query(params): Query<HistoryQuery>,
-) -> Json<Vec<serde_json::Value>> {
-    let collection = state.db.collection::<serde_json::Value>("messages");
-    let limit = params.limit.unwrap_or(50);
-
-    let filter = if let Some(group_id) = params.group_id {
-        doc! { "is_group": true, "target_id": group_id }
-    } else if let Some(target_id) = params.target_id {
-        // P2P: (sender=Me AND target=Other) OR (sender=Other AND target=Me)
-        doc! {
-            "$or": [
-                { "sender_id": &params.user_id, "target_id": &target_id },
-                { "sender_id": &target_id, "target_id": &params.user_id }
-            ]
-        }
-    } else {
-        doc! {}
-    };
-
-    let find_options = mongodb::options::FindOptions::builder()
-        .sort(doc! { "timestamp": -1 })
-        .limit(limit)
-        .build();
-
-    let mut cursor = collection.find(filter, find_options).await.unwrap();
-    let mut messages = Vec::new();
-    while let Ok(Some(doc)) = cursor.try_next().await {
-        messages.push(doc);
-    }
-    // Reverse to chronological order for chat UI
-    messages.reverse();
-    Json(messages)
-}
-
-async fn get_calls(
-    State(state): State<AppState>,
-    Query(params): Query<HistoryQuery>,
-) -> Json<Vec<serde_json::Value>> {
-    let collection = state.db.collection::<serde_json::Value>("calls");
-    let limit = params.limit.unwrap_or(20);
-
-    // Calls where I am caller OR callee
-    let filter = doc! {
-        "$or": [
-            { "caller_id": &params.user_id },
-            { "callee_id": &params.user_id }
-        ]
-    };
-
-    let find_options = mongodb::options::FindOptions::builder()
-        .sort(doc! { "timestamp": -1 })
-        .limit(limit)
-        .build();
-
-    let mut cursor = collection.find(filter, find_options).await.unwrap();
-    let mut calls = Vec::new();
-    while let Ok(Some(doc)) = cursor.try_next().await {
-        calls.push(doc);
-    }
-    Json(calls)
-}
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    // Initialize Database
-    let db_handle = match db::connect_db().await {
-        Ok(db) => db,
-        Err(e) => {
-            eprintln!("Failed to connect to MongoDB: {}", e);
+    // Initialize State
+    // STORE_BACKEND=memory runs the hub without MongoDB (local dev only, nothing is persisted,
+    // and with no Social groups every group join and group send is refused).
+    let backend = std::env::var("STORE_BACKEND").unwrap_or_else(|_| "mongo".to_string());
+    let state = if backend == "memory" {
+        println!("Using in-memory message store");
+        AppState::in_memory()
+    } else {
+        // Initialize Database
+        let db_handle = match db::connect_db().await {
+            Ok(db) => db,
+            Err(e) => {
+                eprintln!("Failed to connect to MongoDB: {}", e);
+                return;
+            }
+        };
+        let social_db = match db::connect_social_db().await {
+            Ok(db) => db,
+            Err(e) => {
+                eprintln!("Failed to connect to the Social database: {}", e);
+                return;
+            }
+        };
+        let store = Arc::new(MongoStore::new(db_handle));
+        if let Err(e) = store.ensure_indexes().await {
+            eprintln!("Failed to create MongoDB indexes: {}", e);
             return;
         }
+        let groups = Arc::new(SocialGroups::new(social_db));
+        AppState::new(store.clone(), store.clone(), groups, store.clone(), store)
     };
 
-    // Initialize State
-    let state = AppState {
-        connections: Arc::new(DashMap::new()),
-        groups: Arc::new(DashMap::new()),
-        db: db_handle,
+    // Cross-node fan-out, presence and rate limits. Without REDIS_URL this node only serves
+    // its own sockets, and every budget applies per node.
+    let heartbeat = HeartbeatConfig::from_env();
+    let state = match std::env::var("REDIS_URL") {
+        Ok(url) => {
+            // A session outlives one missed heartbeat refresh, not a dead node.
+            let session_ttl = heartbeat.interval + heartbeat.timeout;
+            match tokio::try_join!(
+                RedisBus::connect(&url),
+                RedisSessions::connect(&url, session_ttl),
+                RedisBuckets::connect(&url)
+            ) {
+                Ok((bus, sessions, buckets)) => state
+                    .with_bus(Arc::new(bus))
+                    .with_sessions(Arc::new(sessions))
+                    .with_rate_buckets(Arc::new(buckets)),
+                Err(e) => {
+                    eprintln!("Failed to connect to Redis: {}", e);
+                    return;
+                }
+            }
+        }
+        Err(_) => {
+            println!("REDIS_URL not set, running as a single node");
+            state
+        }
     };
+    let mut state = state
+        .with_heartbeat(heartbeat)
+        .with_call_config(CallConfig::from_env())
+        .with_ice_config(IceConfig::from_env())
+        .with_rate_limits(RateLimitConfig::from_env());
+    if let Some(capacity) = std::env::var("OUTBOUND_QUEUE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        state = state.with_queue_capacity(capacity);
+    }
+    state.spawn_bus_listener();
 
     // Setup routes
-    let app = Router::new()
-        .route("/", get(|| async { "Realtime Hub is running!" }))
-        .route("/ws", get(ws::ws_handler))
-        .route("/messages", get(get_messages))
-        .route("/calls", get(get_calls))
-        .with_state(state);
+    let app = api::router(state);
 
     // Address
     let addr = SocketAddr::from(([0, 0, 0, 0], 3004));
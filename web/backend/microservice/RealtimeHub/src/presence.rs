@@ -0,0 +1,190 @@
+use dashmap::{DashMap, DashSet};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use ts_rs::TS;
+
+use crate::auth::UserId;
+use crate::bus::Route;
+use crate::protocol::ServerMessage;
+use crate::ws::{now_millis, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, TS)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Online,
+    Away,
+    Dnd,
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct Presence {
+    pub user_id: String,
+    pub status: Status,
+    /// When the user's last session closed; `None` while connected or if never seen.
+    #[ts(type = "number | null")]
+    pub last_seen: Option<i64>,
+}
+
+/// Presence subscriptions of the sockets on this node. Who is online, their
+/// status and last-seen live in the cluster-wide `SessionStore`.
+#[derive(Default)]
+pub struct PresenceService {
+    /// Watched user -> users who asked for their presence.
+    subscribers: DashMap<UserId, DashSet<UserId>>,
+}
+
+impl PresenceService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, watcher: &UserId, user_id: &UserId) {
+        self.subscribers
+            .entry(user_id.clone())
+            .or_default()
+            .insert(watcher.clone());
+    }
+
+    pub fn unsubscribe(&self, watcher: &UserId, user_id: &UserId) {
+        if let Some(watchers) = self.subscribers.get(user_id) {
+            watchers.remove(watcher);
+        }
+    }
+
+    /// Drops every subscription the watcher holds.
+    pub fn unsubscribe_all(&self, watcher: &UserId) {
+        for watchers in self.subscribers.iter() {
+            watchers.remove(watcher);
+        }
+        self.subscribers.retain(|_, watchers| !watchers.is_empty());
+    }
+
+    pub fn subscribers_of(&self, user_id: &UserId) -> Vec<UserId> {
+        self.subscribers
+            .get(user_id)
+            .map(|watchers| watchers.iter().map(|w| w.key().clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Current presence of a user across all nodes.
+pub async fn presence_of(state: &AppState, user_id: &UserId) -> Presence {
+    let online = match state.sessions.session_count(user_id.as_str()).await {
+        Ok(count) => count > 0,
+        Err(e) => {
+            eprintln!("Failed to load sessions of {}: {}", user_id, e);
+            state.connections.is_online(user_id.as_str())
+        }
+    };
+    if online {
+        let status = state
+            .sessions
+            .status(user_id.as_str())
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load status of {}: {}", user_id, e);
+                None
+            });
+        Presence {
+            user_id: user_id.to_string(),
+            status: status.unwrap_or(Status::Online),
+            last_seen: None,
+        }
+    } else {
+        let last_seen = state
+            .sessions
+            .last_seen(user_id.as_str())
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load last seen of {}: {}", user_id, e);
+                None
+            });
+        Presence {
+            user_id: user_id.to_string(),
+            status: Status::Offline,
+            last_seen,
+        }
+    }
+}
+
+pub fn presence_frame(presence: &Presence) -> String {
+    ServerMessage::Presence(presence.clone()).to_frame()
+}
+
+/// Users with a socket on this node who see the user's presence: their
+/// subscribers, plus the members of the listed groups.
+pub fn audience(state: &AppState, user_id: &UserId, group_ids: &[String]) -> HashSet<UserId> {
+    let mut audience: HashSet<UserId> =
+        state.presence.subscribers_of(user_id).into_iter().collect();
+    for group_id in group_ids {
+        if let Some(members) = state.groups.get(group_id) {
+            audience.extend(members.iter().map(|member| member.key().clone()));
+        }
+    }
+    audience.remove(user_id);
+    audience
+}
+
+/// Tells the user's audience on every node about their current presence.
+pub async fn broadcast_presence(state: &AppState, user_id: &UserId) {
+    let frame = presence_frame(&presence_of(state, user_id).await);
+    let group_ids = state
+        .groups
+        .iter()
+        .filter(|members| members.contains(user_id))
+        .map(|members| members.key().clone())
+        .collect();
+    let route = Route::Watchers {
+        user_id: user_id.to_string(),
+        group_ids,
+    };
+    state.deliver(route, frame).await;
+}
+
+/// Records a new session; the user's first one anywhere makes them online.
+pub async fn session_opened(state: &AppState, user_id: &UserId, session_id: &str) {
+    let sessions = match state
+        .sessions
+        .add_session(user_id.as_str(), session_id)
+        .await
+    {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Failed to record session of {}: {}", user_id, e);
+            state.connections.session_count(user_id.as_str())
+        }
+    };
+    if sessions == 1 {
+        broadcast_presence(state, user_id).await;
+    }
+}
+
+/// Forgets a closed session. When it was the user's last one anywhere, they
+/// go offline. Returns whether they did.
+pub async fn session_closed(state: &AppState, user_id: &UserId, session_id: &str) -> bool {
+    let remaining = match state
+        .sessions
+        .remove_session(user_id.as_str(), session_id)
+        .await
+    {
+        Ok(remaining) => remaining,
+        Err(e) => {
+            eprintln!("Failed to drop session of {}: {}", user_id, e);
+            state.connections.session_count(user_id.as_str())
+        }
+    };
+    if remaining > 0 {
+        return false;
+    }
+    if let Err(e) = state
+        .sessions
+        .mark_seen(user_id.as_str(), now_millis())
+        .await
+    {
+        eprintln!("Failed to save last seen of {}: {}", user_id, e);
+    }
+    broadcast_presence(state, user_id).await;
+    true
+}
@@ -0,0 +1,580 @@
+use axum::extract::ws::Message;
+use base64::Engine;
+use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::presence::{Presence, Status};
+use crate::ratelimit::FrameKind;
+use crate::store::{CallRecord, CallStatus, ChatMessage, Conference, Participant, Revision};
+
+/// Longest accepted message content, in characters.
+pub const MAX_CONTENT_LEN: usize = 4000;
+/// Most attachments on one message.
+pub const MAX_ATTACHMENTS: usize = 10;
+/// Longest accepted attachment reference, in bytes.
+pub const MAX_ATTACHMENT_LEN: usize = 2048;
+/// Longest accepted reaction, in bytes. Enough for any emoji ZWJ sequence.
+pub const MAX_EMOJI_LEN: usize = 32;
+/// Longest accepted ciphertext of an `encrypted` message, in base64 bytes.
+/// It carries one copy per recipient device, so it is allowed more room.
+pub const MAX_CIPHERTEXT_LEN: usize = 64 * 1024;
+/// Most users one `subscribe_presence` or `unsubscribe_presence` may name.
+pub const MAX_PRESENCE_SUBSCRIPTIONS: usize = 200;
+/// Accepted values of a chat message's `kind`.
+pub const MESSAGE_KINDS: &[&str] = &["text", "image", "video", "audio", "file", "encrypted"];
+/// Kind of an end-to-end encrypted DM. Its `content` is base64 ciphertext the
+/// hub stores and relays without reading.
+pub const ENCRYPTED_KIND: &str = "encrypted";
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, TS)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    #[serde(rename = "join")]
+    Join { user_id: String },
+
+    #[serde(rename = "join_group")]
+    JoinGroup { user_id: String, group_id: String },
+
+    #[serde(rename = "leave_group")]
+    LeaveGroup { user_id: String, group_id: String },
+
+    #[serde(rename = "chat")]
+    Chat {
+        target_id: String, // UserId or GroupId
+        is_group: bool,
+        #[ts(optional = nullable)]
+        content: Option<String>,
+        #[ts(optional = nullable)]
+        attachments: Option<Vec<String>>,
+        /// `text`, `image`, `video`, `audio`, `file`, or `encrypted` for a DM
+        /// whose `content` is base64 ciphertext and that has no attachments.
+        kind: String,
+        /// Client-generated id, echoed back in the `ack` and used to drop resends.
+        #[serde(default)]
+        #[ts(optional = nullable)]
+        client_msg_id: Option<String>,
+        /// Message being quoted or answered, from the same conversation.
+        #[serde(default)]
+        #[ts(optional = nullable)]
+        reply_to: Option<String>,
+        /// Top-level message of the thread to post into.
+        #[serde(default)]
+        #[ts(optional = nullable)]
+        thread_root: Option<String>,
+    },
+
+    #[serde(rename = "signal")]
+    Signal {
+        target_id: String,
+        #[ts(type = "unknown")]
+        payload: serde_json::Value,
+    },
+
+    /// Everything up to and including this message reached the client.
+    #[serde(rename = "delivered")]
+    Delivered { message_id: String },
+
+    /// `online`, `away` or `dnd`; `offline` is only ever set by disconnecting.
+    #[serde(rename = "set_status")]
+    SetStatus { status: Status },
+
+    #[serde(rename = "subscribe_presence")]
+    SubscribePresence { user_ids: Vec<String> },
+
+    #[serde(rename = "unsubscribe_presence")]
+    UnsubscribePresence { user_ids: Vec<String> },
+
+    /// Ephemeral and throttled; relayed to the conversation, never stored.
+    #[serde(rename = "typing")]
+    Typing { target_id: String, is_group: bool },
+
+    /// The user has read the conversation up to and including `message_id`.
+    #[serde(rename = "read")]
+    Read {
+        target_id: String,
+        is_group: bool,
+        message_id: String,
+    },
+
+    /// Replaces the content of one of the user's own messages.
+    #[serde(rename = "edit_message")]
+    EditMessage {
+        message_id: String,
+        #[ts(optional = nullable)]
+        content: Option<String>,
+        #[ts(optional = nullable)]
+        attachments: Option<Vec<String>>,
+    },
+
+    /// Deletes one of the user's own messages, leaving a tombstone.
+    #[serde(rename = "delete_message")]
+    DeleteMessage { message_id: String },
+
+    #[serde(rename = "react")]
+    React { message_id: String, emoji: String },
+
+    #[serde(rename = "unreact")]
+    Unreact { message_id: String, emoji: String },
+
+    /// Rings the callee. The call id comes back in the first `call_state`.
+    #[serde(rename = "call_invite")]
+    CallInvite {
+        target_id: String,
+        #[serde(default)]
+        #[ts(as = "Option<bool>", optional)]
+        video: bool,
+    },
+
+    /// The callee answers.
+    #[serde(rename = "call_accept")]
+    CallAccept { call_id: String },
+
+    /// The callee turns the call down.
+    #[serde(rename = "call_decline")]
+    CallDecline { call_id: String },
+
+    /// Media is flowing; either party may report it.
+    #[serde(rename = "call_connected")]
+    CallConnected { call_id: String },
+
+    /// Hangs up, or gives up on a call still ringing.
+    #[serde(rename = "call_end")]
+    CallEnd { call_id: String },
+
+    /// Joins the group's call, starting one if none is running.
+    #[serde(rename = "conference_join")]
+    ConferenceJoin { group_id: String },
+
+    #[serde(rename = "conference_leave")]
+    ConferenceLeave { group_id: String },
+
+    /// Offer, answer or ICE candidate for one other participant of the group's call.
+    #[serde(rename = "conference_signal")]
+    ConferenceSignal {
+        group_id: String,
+        target_id: String,
+        #[ts(type = "unknown")]
+        payload: serde_json::Value,
+    },
+}
+
+impl ClientMessage {
+    /// Budget the frame is charged to.
+    pub(crate) fn rate_class(&self) -> FrameKind {
+        match self {
+            ClientMessage::Chat { .. }
+            | ClientMessage::EditMessage { .. }
+            | ClientMessage::DeleteMessage { .. }
+            | ClientMessage::React { .. }
+            | ClientMessage::Unreact { .. } => FrameKind::Chat,
+            ClientMessage::Signal { .. }
+            | ClientMessage::CallInvite { .. }
+            | ClientMessage::CallAccept { .. }
+            | ClientMessage::CallDecline { .. }
+            | ClientMessage::CallConnected { .. }
+            | ClientMessage::CallEnd { .. }
+            | ClientMessage::ConferenceJoin { .. }
+            | ClientMessage::ConferenceLeave { .. }
+            | ClientMessage::ConferenceSignal { .. } => FrameKind::Signal,
+            ClientMessage::Typing { .. } => FrameKind::Typing,
+            ClientMessage::Join { .. }
+            | ClientMessage::JoinGroup { .. }
+            | ClientMessage::LeaveGroup { .. }
+            | ClientMessage::Delivered { .. }
+            | ClientMessage::Read { .. }
+            | ClientMessage::SetStatus { .. }
+            | ClientMessage::SubscribePresence { .. }
+            | ClientMessage::UnsubscribePresence { .. } => FrameKind::Control,
+        }
+    }
+
+    pub fn client_msg_id(&self) -> Option<&str> {
+        match self {
+            ClientMessage::Chat { client_msg_id, .. } => client_msg_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Parses a text frame, or says why it is not a valid client message.
+    pub fn parse(text: &str) -> Result<ClientMessage, Rejection> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| Rejection {
+            code: ErrorCode::Malformed,
+            message: format!("invalid JSON: {}", e),
+            client_msg_id: None,
+        })?;
+        let client_msg_id = value
+            .get("client_msg_id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string);
+        if !value.get("type").is_some_and(|t| t.is_string()) {
+            return Err(Rejection {
+                code: ErrorCode::Malformed,
+                message: "frame has no `type`".to_string(),
+                client_msg_id,
+            });
+        }
+        serde_json::from_value(value).map_err(|e| {
+            let message = e.to_string();
+            let code = if message.starts_with("unknown variant") {
+                ErrorCode::UnknownType
+            } else {
+                ErrorCode::Invalid
+            };
+            Rejection {
+                code,
+                message,
+                client_msg_id,
+            }
+        })
+    }
+
+    /// Checks limits serde cannot express. Returns what is wrong, if anything.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ClientMessage::Chat {
+                is_group,
+                content,
+                attachments,
+                kind,
+                ..
+            } => {
+                if !MESSAGE_KINDS.contains(&kind.as_str()) {
+                    return Err(format!(
+                        "unknown kind `{}`, expected one of {}",
+                        kind,
+                        MESSAGE_KINDS.join(", ")
+                    ));
+                }
+                if kind == ENCRYPTED_KIND {
+                    return validate_ciphertext(
+                        *is_group,
+                        content.as_deref(),
+                        attachments.as_deref(),
+                    );
+                }
+                let has_content = content.as_deref().is_some_and(|c| !c.is_empty());
+                let has_attachments = attachments.as_ref().is_some_and(|a| !a.is_empty());
+                if !has_content && !has_attachments {
+                    return Err("message has neither content nor attachments".to_string());
+                }
+                validate_body(content.as_deref(), attachments.as_deref())
+            }
+            ClientMessage::EditMessage {
+                content,
+                attachments,
+                ..
+            } => {
+                if content.is_none() && attachments.is_none() {
+                    return Err("edit changes neither content nor attachments".to_string());
+                }
+                validate_body(content.as_deref(), attachments.as_deref())
+            }
+            ClientMessage::React { emoji, .. } | ClientMessage::Unreact { emoji, .. } => {
+                if valid_emoji(emoji) {
+                    Ok(())
+                } else {
+                    Err(format!("invalid reaction `{}`", emoji))
+                }
+            }
+            ClientMessage::SubscribePresence { user_ids }
+            | ClientMessage::UnsubscribePresence { user_ids } => {
+                if user_ids.len() > MAX_PRESENCE_SUBSCRIPTIONS {
+                    return Err(format!(
+                        "{} users, at most {} allowed",
+                        user_ids.len(),
+                        MAX_PRESENCE_SUBSCRIPTIONS
+                    ));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The `user_id` the frame claims to come from, for frames that carry one.
+    pub fn claimed_user_id(&self) -> Option<&str> {
+        match self {
+            ClientMessage::Join { user_id }
+            | ClientMessage::JoinGroup { user_id, .. }
+            | ClientMessage::LeaveGroup { user_id, .. } => Some(user_id),
+            _ => None,
+        }
+    }
+}
+
+fn validate_body(content: Option<&str>, attachments: Option<&[String]>) -> Result<(), String> {
+    if let Some(content) = content {
+        let len = content.chars().count();
+        if len > MAX_CONTENT_LEN {
+            return Err(format!(
+                "content is {} characters, at most {} allowed",
+                len, MAX_CONTENT_LEN
+            ));
+        }
+    }
+    if let Some(attachments) = attachments {
+        if attachments.len() > MAX_ATTACHMENTS {
+            return Err(format!(
+                "{} attachments, at most {} allowed",
+                attachments.len(),
+                MAX_ATTACHMENTS
+            ));
+        }
+        if attachments
+            .iter()
+            .any(|a| a.is_empty() || a.len() > MAX_ATTACHMENT_LEN)
+        {
+            return Err("attachment references must be 1 to 2048 bytes".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// An encrypted message is a DM of one base64 blob; attachments travel
+/// inside it. Only its size is checked.
+fn validate_ciphertext(
+    is_group: bool,
+    content: Option<&str>,
+    attachments: Option<&[String]>,
+) -> Result<(), String> {
+    if is_group {
+        return Err("encrypted messages are for direct messages only".to_string());
+    }
+    if attachments.is_some_and(|a| !a.is_empty()) {
+        return Err("encrypted messages carry attachments inside the ciphertext".to_string());
+    }
+    let content = content.unwrap_or_default();
+    if content.len() > MAX_CIPHERTEXT_LEN {
+        return Err(format!(
+            "ciphertext is {} bytes, at most {} allowed",
+            content.len(),
+            MAX_CIPHERTEXT_LEN
+        ));
+    }
+    match base64::engine::general_purpose::STANDARD.decode(content) {
+        Ok(bytes) if !bytes.is_empty() => Ok(()),
+        _ => Err("content of an encrypted message must be base64 ciphertext".to_string()),
+    }
+}
+
+/// Reactions are stored as document keys, so `.` and a leading `$` are out.
+fn valid_emoji(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.len() <= MAX_EMOJI_LEN
+        && !emoji.contains('.')
+        && !emoji.starts_with('$')
+}
+
+/// Why a client frame was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not JSON, or no `type`.
+    Malformed,
+    /// A `type` the hub does not know.
+    UnknownType,
+    /// Known type, but missing fields or values out of bounds.
+    Invalid,
+    /// Not allowed for this user, including claiming to be someone else.
+    Forbidden,
+    /// The referenced message does not exist or is not the user's.
+    NotFound,
+    RateLimited,
+    Unsupported,
+    /// Both parties called each other at once. The call that rang first
+    /// stands; answer it instead.
+    Glare,
+    /// The group call has as many participants as it may.
+    Full,
+}
+
+/// A client frame the hub could not parse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub code: ErrorCode,
+    pub message: String,
+    pub client_msg_id: Option<String>,
+}
+
+impl From<Rejection> for ServerMessage {
+    fn from(rejection: Rejection) -> Self {
+        ServerMessage::error(rejection.code, rejection.message, rejection.client_msg_id)
+    }
+}
+
+/// Every frame the hub sends to clients.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// A message in one of the user's conversations, live or replayed.
+    Chat(ChatMessage),
+
+    /// The sending socket's message was stored.
+    Ack {
+        client_msg_id: Option<String>,
+        message_id: String,
+        #[ts(type = "number")]
+        timestamp: i64,
+    },
+
+    /// Ends the offline replay on connect.
+    CaughtUp {
+        #[ts(type = "number")]
+        count: i64,
+    },
+
+    Signal {
+        sender_id: String,
+        #[ts(type = "unknown")]
+        payload: serde_json::Value,
+    },
+
+    Presence(Presence),
+
+    Typing {
+        sender_id: String,
+        target_id: String,
+        is_group: bool,
+    },
+
+    Read {
+        user_id: String,
+        target_id: String,
+        is_group: bool,
+        message_id: String,
+        #[ts(type = "number")]
+        timestamp: i64,
+    },
+
+    MessageEdited {
+        message_id: String,
+        sender_id: String,
+        target_id: String,
+        is_group: bool,
+        content: Option<String>,
+        attachments: Option<Vec<String>>,
+        #[ts(type = "number | null")]
+        edited_at: Option<i64>,
+    },
+
+    MessageDeleted {
+        message_id: String,
+        sender_id: String,
+        target_id: String,
+        is_group: bool,
+        #[ts(type = "number | null")]
+        deleted_at: Option<i64>,
+    },
+
+    Reaction {
+        message_id: String,
+        target_id: String,
+        is_group: bool,
+        user_id: String,
+        emoji: String,
+        added: bool,
+        /// Everyone who reacted with `emoji` now, in order.
+        reactors: Vec<String>,
+    },
+
+    /// A reply in a group thread the user took part in, for when they are
+    /// not in the group's room.
+    ThreadReply(ChatMessage),
+
+    /// A call changed state. Both parties get every change, on every device.
+    CallState(CallRecord),
+
+    /// Who is in a group's call, sent to the group's room on every join and
+    /// leave. `ended` once the last participant left.
+    ConferenceRoster {
+        call_id: String,
+        group_id: String,
+        /// In the order they joined.
+        participants: Vec<String>,
+        joined: Option<String>,
+        left: Option<String>,
+        ended: bool,
+    },
+
+    /// Offer, answer or ICE candidate from another participant of a group call.
+    ConferenceSignal {
+        call_id: String,
+        group_id: String,
+        sender_id: String,
+        #[ts(type = "unknown")]
+        payload: serde_json::Value,
+    },
+
+    /// A client frame was refused.
+    Error {
+        code: ErrorCode,
+        message: String,
+        /// `client_msg_id` of the refused frame, if it had one.
+        client_msg_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[ts(optional, as = "Option<f64>")]
+        retry_after_ms: Option<u64>,
+    },
+}
+
+impl ServerMessage {
+    pub fn error(
+        code: ErrorCode,
+        message: impl Into<String>,
+        client_msg_id: Option<String>,
+    ) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+            client_msg_id,
+            retry_after_ms: None,
+        }
+    }
+
+    /// The frame as sent on the socket and the bus.
+    pub fn to_frame(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn to_message(&self) -> Message {
+        Message::Text(self.to_frame())
+    }
+}
+
+/// JSON Schema of the frames clients send.
+pub fn client_schema() -> RootSchema {
+    schema_for!(ClientMessage)
+}
+
+/// JSON Schema of the frames the hub sends.
+pub fn server_schema() -> RootSchema {
+    schema_for!(ServerMessage)
+}
+
+/// TypeScript declarations of both directions and every type they use, as
+/// one module.
+pub fn typescript() -> String {
+    let decls = [
+        ClientMessage::decl(),
+        ServerMessage::decl(),
+        ChatMessage::decl(),
+        Revision::decl(),
+        Presence::decl(),
+        Status::decl(),
+        ErrorCode::decl(),
+        CallRecord::decl(),
+        CallStatus::decl(),
+        Conference::decl(),
+        Participant::decl(),
+    ];
+    let mut module =
+        String::from("// Generated by `cargo run --bin export_protocol`. Do not edit.\n");
+    for decl in decls {
+        module.push_str("\nexport ");
+        module.push_str(&decl);
+        module.push('\n');
+    }
+    module
+}
@@ -0,0 +1,417 @@
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::UserId;
+use crate::bus::BusResult;
+use crate::ws::now_millis;
+
+/// Refused frames a user may rack up within this window before being disconnected.
+pub const VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Frame types, and REST calls, with their own budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    /// Everything that writes a message: chat, edits, deletes, reactions.
+    Chat,
+    Signal,
+    Typing,
+    /// Everything else: receipts, rooms, status and presence subscriptions.
+    Control,
+    /// `GET /keys/:user_id` by one requester, whoever it is for. Each one
+    /// uses up prekeys.
+    KeyBundle,
+    /// `GET /keys/:user_id` for one particular user, by anyone.
+    PeerKeyBundle,
+}
+
+impl FrameKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameKind::Chat => "chat",
+            FrameKind::Signal => "signal",
+            FrameKind::Typing => "typing",
+            FrameKind::Control => "control",
+            FrameKind::KeyBundle => "key_bundle",
+            FrameKind::PeerKeyBundle => "peer_key_bundle",
+        }
+    }
+}
+
+/// Token bucket: `burst` frames at once, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+impl Budget {
+    pub const fn new(burst: u32, per_second: u32) -> Self {
+        Budget {
+            burst: burst as f64,
+            per_second: per_second as f64,
+        }
+    }
+
+    /// Parses `"<burst>,<per_second>"`, e.g. `"20,5"`.
+    fn parse(raw: &str) -> Option<Self> {
+        let (burst, per_second) = raw.split_once(',')?;
+        let burst: f64 = burst.trim().parse().ok()?;
+        let per_second: f64 = per_second.trim().parse().ok()?;
+        (burst >= 1.0 && per_second > 0.0).then_some(Budget { burst, per_second })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    pub chat: Budget,
+    pub signal: Budget,
+    pub typing: Budget,
+    pub control: Budget,
+    pub key_bundle: Budget,
+    pub peer_key_bundle: Budget,
+    /// Refused frames within `VIOLATION_WINDOW` that get the user disconnected.
+    pub max_violations: u32,
+}
+
+impl RateLimitConfig {
+    pub fn budget(&self, kind: FrameKind) -> Budget {
+        match kind {
+            FrameKind::Chat => self.chat,
+            FrameKind::Signal => self.signal,
+            FrameKind::Typing => self.typing,
+            FrameKind::Control => self.control,
+            FrameKind::KeyBundle => self.key_bundle,
+            FrameKind::PeerKeyBundle => self.peer_key_bundle,
+        }
+    }
+
+    /// Reads `RATE_LIMIT_CHAT`, `RATE_LIMIT_SIGNAL`, `RATE_LIMIT_TYPING`,
+    /// `RATE_LIMIT_CONTROL`, `RATE_LIMIT_KEY_BUNDLE`, `RATE_LIMIT_PEER_KEY_BUNDLE`
+    /// (each `"<burst>,<per_second>"`) and `RATE_LIMIT_MAX_VIOLATIONS`, keeping the
+    /// default for anything unset or unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let budget = |name: &str, default: Budget| {
+            std::env::var(name)
+                .ok()
+                .and_then(|raw| Budget::parse(&raw))
+                .unwrap_or(default)
+        };
+        RateLimitConfig {
+            chat: budget("RATE_LIMIT_CHAT", defaults.chat),
+            signal: budget("RATE_LIMIT_SIGNAL", defaults.signal),
+            typing: budget("RATE_LIMIT_TYPING", defaults.typing),
+            control: budget("RATE_LIMIT_CONTROL", defaults.control),
+            key_bundle: budget("RATE_LIMIT_KEY_BUNDLE", defaults.key_bundle),
+            peer_key_bundle: budget("RATE_LIMIT_PEER_KEY_BUNDLE", defaults.peer_key_bundle),
+            max_violations: std::env::var("RATE_LIMIT_MAX_VIOLATIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_violations),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            chat: Budget::new(20, 5),
+            // Call setup sends a burst of ICE candidates.
+            signal: Budget::new(60, 20),
+            typing: Budget::new(5, 1),
+            control: Budget::new(60, 20),
+            // A bundle per peer device is all a client needs to start
+            // sessions; more only drains the peer's prekeys.
+            key_bundle: Budget {
+                burst: 30.0,
+                per_second: 0.2,
+            },
+            // Shared by everyone fetching the same user's keys.
+            peer_key_bundle: Budget {
+                burst: 20.0,
+                per_second: 0.1,
+            },
+            max_violations: 20,
+        }
+    }
+}
+
+/// What to do with a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Refuse this frame; the budget has room again after `retry_after`.
+    Deny {
+        retry_after: Duration,
+    },
+    /// Too many refused frames; drop the connection.
+    Disconnect,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    budget: Budget,
+}
+
+struct Strikes {
+    count: u32,
+    since: Instant,
+}
+
+/// Where the token buckets are kept, by key. Nodes sharing one store share
+/// every budget, so running more nodes does not multiply them.
+#[async_trait]
+pub trait BucketStore: Send + Sync {
+    /// Takes a token from each bucket if every one of them has one. Returns
+    /// `None` if they did, or how long until they all will; then none is
+    /// taken.
+    async fn take(&self, buckets: &[(String, Budget)]) -> BusResult<Option<Duration>>;
+}
+
+/// In-process buckets. Hub states sharing one instance share budgets, like
+/// nodes sharing Redis; a node with its own instance limits only its sockets.
+pub struct LocalBuckets {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    last_prune: Mutex<Instant>,
+}
+
+impl LocalBuckets {
+    pub fn new() -> Self {
+        LocalBuckets {
+            buckets: Mutex::new(HashMap::new()),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn take_now(&self, takes: &[(String, Budget)]) -> Option<Duration> {
+        self.prune();
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut wait = Duration::ZERO;
+        for (key, budget) in takes {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: budget.burst,
+                updated: now,
+                budget: *budget,
+            });
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * budget.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(budget.burst);
+            bucket.updated = now;
+            bucket.budget = *budget;
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64(
+                    (1.0 - bucket.tokens) / budget.per_second,
+                ));
+            }
+        }
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+        for (key, _) in takes {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        None
+    }
+
+    /// Drops buckets that have refilled completely, at most once per
+    /// violation window.
+    fn prune(&self) {
+        let mut last_prune = self.last_prune.lock().unwrap();
+        if last_prune.elapsed() < VIOLATION_WINDOW {
+            return;
+        }
+        *last_prune = Instant::now();
+        drop(last_prune);
+
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            let refill = bucket.updated.elapsed().as_secs_f64() * bucket.budget.per_second;
+            bucket.tokens + refill < bucket.budget.burst
+        });
+    }
+}
+
+impl Default for LocalBuckets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl BucketStore for LocalBuckets {
+    async fn take(&self, buckets: &[(String, Budget)]) -> BusResult<Option<Duration>> {
+        Ok(self.take_now(buckets))
+    }
+}
+
+/// Refills the buckets at `KEYS` up to `ARGV[1]` milliseconds since epoch,
+/// with `ARGV[2 * i]` and `ARGV[2 * i + 1]` the burst and per-second refill
+/// of `KEYS[i]`, and takes a token from each if all have one. Returns 0 if
+/// they did, otherwise the milliseconds until they all will. Buckets expire
+/// once they would be full again.
+const TAKE_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local wait = 0
+local buckets = {}
+for i, key in ipairs(KEYS) do
+    local burst = tonumber(ARGV[2 * i])
+    local per_ms = tonumber(ARGV[2 * i + 1]) / 1000
+    local stored = redis.call('HMGET', key, 'tokens', 'updated')
+    local tokens = tonumber(stored[1]) or burst
+    local updated = tonumber(stored[2]) or now
+    tokens = math.min(burst, tokens + math.max(0, now - updated) * per_ms)
+    if tokens < 1 then
+        wait = math.max(wait, math.ceil((1 - tokens) / per_ms))
+    end
+    buckets[i] = { key, burst, per_ms, tokens - 1 }
+end
+if wait > 0 then
+    return wait
+end
+for _, bucket in ipairs(buckets) do
+    local key, burst, per_ms, tokens = unpack(bucket)
+    redis.call('HSET', key, 'tokens', tostring(tokens), 'updated', now)
+    redis.call('PEXPIRE', key, math.ceil((burst - tokens) / per_ms) + 1)
+end
+return 0
+"#;
+
+/// Buckets in Redis, shared by all hub instances behind the gateway. Each
+/// take is one script run, so nodes racing on a bucket cannot both spend
+/// its last token.
+pub struct RedisBuckets {
+    conn: redis::aio::ConnectionManager,
+    script: redis::Script,
+}
+
+impl RedisBuckets {
+    pub async fn connect(url: &str) -> BusResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = redis::aio::ConnectionManager::new(client).await?;
+        Ok(RedisBuckets {
+            conn,
+            script: redis::Script::new(TAKE_SCRIPT),
+        })
+    }
+}
+
+#[async_trait]
+impl BucketStore for RedisBuckets {
+    async fn take(&self, buckets: &[(String, Budget)]) -> BusResult<Option<Duration>> {
+        let mut invocation = self.script.prepare_invoke();
+        invocation.arg(now_millis());
+        for (key, budget) in buckets {
+            invocation
+                .key(format!("realtime_hub:rate:{}", key))
+                .arg(budget.burst)
+                .arg(budget.per_second);
+        }
+        let wait_ms: u64 = invocation.invoke_async(&mut self.conn.clone()).await?;
+        Ok((wait_ms > 0).then(|| Duration::from_millis(wait_ms)))
+    }
+}
+
+/// Per-user, per-frame-type token buckets, shared by all of a user's
+/// sessions on every node that uses the same `BucketStore`. REST calls may
+/// draw on other buckets too, such as one per user whose keys are fetched.
+/// Strikes are counted per node, as they only decide when to drop this
+/// node's sockets.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<dyn BucketStore>,
+    /// Used while `buckets` cannot be reached, so limits still hold per node.
+    fallback: LocalBuckets,
+    strikes: DashMap<UserId, Strikes>,
+    last_prune: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self::with_buckets(config, Arc::new(LocalBuckets::new()))
+    }
+
+    pub fn with_buckets(config: RateLimitConfig, buckets: Arc<dyn BucketStore>) -> Self {
+        RateLimiter {
+            config,
+            buckets,
+            fallback: LocalBuckets::new(),
+            strikes: DashMap::new(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config
+    }
+
+    pub fn buckets(&self) -> Arc<dyn BucketStore> {
+        self.buckets.clone()
+    }
+
+    pub async fn check(&self, user_id: &UserId, kind: FrameKind) -> Verdict {
+        self.prune();
+        let Some(retry_after) = self.take(&[(kind, user_id.as_str())]).await else {
+            return Verdict::Allow;
+        };
+
+        let now = Instant::now();
+        let mut strikes = self.strikes.entry(user_id.clone()).or_insert(Strikes {
+            count: 0,
+            since: now,
+        });
+        if now.duration_since(strikes.since) > VIOLATION_WINDOW {
+            strikes.count = 0;
+            strikes.since = now;
+        }
+        strikes.count += 1;
+        if strikes.count >= self.config.max_violations {
+            Verdict::Disconnect
+        } else {
+            Verdict::Deny { retry_after }
+        }
+    }
+
+    /// Takes a token from the `kind` bucket of each key, or from none of them
+    /// if any is empty. Returns how long until all have one again if so.
+    /// Refusals here are no strikes; REST callers just get a 429.
+    pub async fn take(&self, buckets: &[(FrameKind, &str)]) -> Option<Duration> {
+        let takes: Vec<(String, Budget)> = buckets
+            .iter()
+            .map(|(kind, key)| {
+                let budget = self.config.budget(*kind);
+                (format!("{}:{}", kind.as_str(), key), budget)
+            })
+            .collect();
+        match self.buckets.take(&takes).await {
+            Ok(wait) => wait,
+            Err(e) => {
+                eprintln!("Failed to reach rate limit buckets: {}", e);
+                self.fallback.take_now(&takes)
+            }
+        }
+    }
+
+    /// Drops expired strikes, at most once per violation window.
+    fn prune(&self) {
+        let mut last_prune = self.last_prune.lock().unwrap();
+        if last_prune.elapsed() < VIOLATION_WINDOW {
+            return;
+        }
+        *last_prune = Instant::now();
+        drop(last_prune);
+
+        self.strikes
+            .retain(|_, strikes| strikes.since.elapsed() <= VIOLATION_WINDOW);
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitConfig::default())
+    }
+}
@@ -0,0 +1,197 @@
+use axum::extract::ws::Message;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+
+use crate::auth::UserId;
+
+pub type SessionId = String;
+
+/// Frames queued per socket before the hub starts shedding load.
+pub const OUTBOUND_QUEUE_CAPACITY: usize = 256;
+
+/// Sending half of one socket's bounded outbound queue.
+///
+/// Ephemeral frames (typing, pings) are dropped once the queue is half full,
+/// which keeps the rest for frames that matter. When a frame that must not be
+/// lost finds the queue full, the session is flagged as overflowed and its
+/// writer closes the socket; the client reconnects and catches up.
+#[derive(Clone)]
+pub struct Outbound {
+    tx: mpsc::Sender<Message>,
+    capacity: usize,
+    overflow: Arc<Notify>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Outbound {
+    pub fn channel(capacity: usize) -> (Outbound, mpsc::Receiver<Message>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let outbound = Outbound {
+            tx,
+            capacity,
+            overflow: Arc::new(Notify::new()),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (outbound, rx)
+    }
+
+    /// Queues a frame the client must get. Returns `false` if the queue was
+    /// full (the session is then closed) or the socket is already gone.
+    pub fn send(&self, msg: Message) -> bool {
+        match self.tx.try_send(msg) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflow.notify_one();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Queues a frame that is fine to lose, unless the queue is half full.
+    pub fn send_ephemeral(&self, msg: Message) -> bool {
+        if self.depth() * 2 >= self.capacity || self.tx.try_send(msg).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Waits for room instead of applying the overflow policy. Only for the
+    /// session's own backlog, such as the offline replay.
+    pub async fn send_wait(&self, msg: Message) -> bool {
+        self.tx.send(msg).await.is_ok()
+    }
+
+    /// Frames queued and not yet written to the socket.
+    pub fn depth(&self) -> usize {
+        self.capacity - self.tx.capacity()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once a frame that must not be lost did not fit.
+    pub async fn overflowed(&self) {
+        self.overflow.notified().await
+    }
+}
+
+/// Queue state of one socket, for diagnostics.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueStats {
+    pub session_id: SessionId,
+    pub queue_depth: usize,
+    pub capacity: usize,
+    /// Ephemeral frames dropped because the queue was filling up.
+    pub dropped: u64,
+}
+
+/// Live sockets, grouped by user. A user signed in on the desktop app and in
+/// the browser at the same time has one session per socket, each with its
+/// own id, and every frame addressed to the user goes to all of them.
+pub struct ConnectionRegistry {
+    users: DashMap<UserId, DashMap<SessionId, Outbound>>,
+    capacity: usize,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::with_capacity(OUTBOUND_QUEUE_CAPACITY)
+    }
+
+    /// Registry whose sessions get outbound queues of `capacity` frames.
+    pub fn with_capacity(capacity: usize) -> Self {
+        ConnectionRegistry {
+            users: DashMap::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds a socket for the user and returns its new session id.
+    pub fn register(&self, user_id: &UserId, outbound: Outbound) -> SessionId {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        self.users
+            .entry(user_id.clone())
+            .or_default()
+            .insert(session_id.clone(), outbound);
+        session_id
+    }
+
+    /// Removes only this session. Returns `true` if it was the user's last one.
+    pub fn unregister(&self, user_id: &UserId, session_id: &str) -> bool {
+        if let Some(sessions) = self.users.get(user_id) {
+            sessions.remove(session_id);
+        }
+        self.users
+            .remove_if(user_id, |_, sessions| sessions.is_empty())
+            .is_some()
+    }
+
+    pub fn is_online(&self, user_id: &str) -> bool {
+        self.users.contains_key(user_id)
+    }
+
+    pub fn session_count(&self, user_id: &str) -> usize {
+        self.users.get(user_id).map_or(0, |sessions| sessions.len())
+    }
+
+    /// Sends to every session of the user. Returns how many sessions it reached.
+    pub fn send_to_user(&self, user_id: &str, msg: Message, ephemeral: bool) -> usize {
+        let Some(sessions) = self.users.get(user_id) else {
+            return 0;
+        };
+        sessions
+            .iter()
+            .filter(|session| {
+                if ephemeral {
+                    session.value().send_ephemeral(msg.clone())
+                } else {
+                    session.value().send(msg.clone())
+                }
+            })
+            .count()
+    }
+
+    /// Queue state of each of the user's sessions.
+    pub fn queue_stats(&self, user_id: &str) -> Vec<QueueStats> {
+        self.users
+            .get(user_id)
+            .map(|sessions| self.stats_of(&sessions))
+            .unwrap_or_default()
+    }
+
+    /// Queue state of every session on this node.
+    pub fn all_queue_stats(&self) -> Vec<QueueStats> {
+        self.users
+            .iter()
+            .flat_map(|sessions| self.stats_of(&sessions))
+            .collect()
+    }
+
+    fn stats_of(&self, sessions: &DashMap<SessionId, Outbound>) -> Vec<QueueStats> {
+        sessions
+            .iter()
+            .map(|session| QueueStats {
+                session_id: session.key().clone(),
+                queue_depth: session.value().depth(),
+                capacity: self.capacity,
+                dropped: session.value().dropped(),
+            })
+            .collect()
+    }
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,219 @@
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+use redis::AsyncCommands;
+use std::time::Duration;
+
+use crate::bus::BusResult;
+use crate::presence::Status;
+use crate::ws::now_millis;
+
+/// Who is connected anywhere in the cluster, with the status they picked and
+/// when they were last seen. Presence is read from here rather than from the
+/// sockets of one node, so every node agrees on it.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Records a new session. Returns how many the user now has on all nodes.
+    async fn add_session(&self, user_id: &str, session_id: &str) -> BusResult<usize>;
+
+    /// Keeps a live session from expiring; called on every heartbeat.
+    async fn refresh_session(&self, user_id: &str, session_id: &str) -> BusResult<()>;
+
+    /// Forgets a closed session. Returns how many the user still has on all nodes.
+    async fn remove_session(&self, user_id: &str, session_id: &str) -> BusResult<usize>;
+
+    async fn session_count(&self, user_id: &str) -> BusResult<usize>;
+
+    /// Status picked with `set_status`; `None` for plain online.
+    async fn status(&self, user_id: &str) -> BusResult<Option<Status>>;
+
+    async fn set_status(&self, user_id: &str, status: Status) -> BusResult<()>;
+
+    /// When the user's last session closed.
+    async fn last_seen(&self, user_id: &str) -> BusResult<Option<i64>>;
+
+    async fn mark_seen(&self, user_id: &str, at: i64) -> BusResult<()>;
+}
+
+/// In-process sessions. Hub states sharing one instance see each other's
+/// sessions, like nodes sharing Redis; this is how the multi-node tests run.
+/// Sessions never expire, since they die with the process that holds them.
+#[derive(Default)]
+pub struct LocalSessions {
+    sessions: DashMap<String, DashSet<String>>,
+    status: DashMap<String, Status>,
+    last_seen: DashMap<String, i64>,
+}
+
+impl LocalSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for LocalSessions {
+    async fn add_session(&self, user_id: &str, session_id: &str) -> BusResult<usize> {
+        let sessions = self.sessions.entry(user_id.to_string()).or_default();
+        sessions.insert(session_id.to_string());
+        Ok(sessions.len())
+    }
+
+    async fn refresh_session(&self, _user_id: &str, _session_id: &str) -> BusResult<()> {
+        Ok(())
+    }
+
+    async fn remove_session(&self, user_id: &str, session_id: &str) -> BusResult<usize> {
+        if let Some(sessions) = self.sessions.get(user_id) {
+            sessions.remove(session_id);
+        }
+        self.sessions
+            .remove_if(user_id, |_, sessions| sessions.is_empty());
+        self.session_count(user_id).await
+    }
+
+    async fn session_count(&self, user_id: &str) -> BusResult<usize> {
+        Ok(self
+            .sessions
+            .get(user_id)
+            .map_or(0, |sessions| sessions.len()))
+    }
+
+    async fn status(&self, user_id: &str) -> BusResult<Option<Status>> {
+        Ok(self.status.get(user_id).map(|status| *status))
+    }
+
+    async fn set_status(&self, user_id: &str, status: Status) -> BusResult<()> {
+        if status == Status::Online {
+            self.status.remove(user_id);
+        } else {
+            self.status.insert(user_id.to_string(), status);
+        }
+        Ok(())
+    }
+
+    async fn last_seen(&self, user_id: &str) -> BusResult<Option<i64>> {
+        Ok(self.last_seen.get(user_id).map(|at| *at))
+    }
+
+    async fn mark_seen(&self, user_id: &str, at: i64) -> BusResult<()> {
+        self.last_seen.insert(user_id.to_string(), at);
+        Ok(())
+    }
+}
+
+/// Sessions in Redis, shared by all hub instances behind the gateway. Each
+/// user has a sorted set of session ids scored by when they expire, so the
+/// sessions of a node that died without closing them drop out after `ttl`.
+pub struct RedisSessions {
+    conn: redis::aio::ConnectionManager,
+    ttl: Duration,
+}
+
+impl RedisSessions {
+    /// `ttl` should outlast the heartbeat interval, as that is when live
+    /// sessions are refreshed.
+    pub async fn connect(url: &str, ttl: Duration) -> BusResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = redis::aio::ConnectionManager::new(client).await?;
+        Ok(RedisSessions { conn, ttl })
+    }
+
+    fn ttl_millis(&self) -> i64 {
+        self.ttl.as_millis() as i64
+    }
+}
+
+fn sessions_key(user_id: &str) -> String {
+    format!("realtime_hub:sessions:{}", user_id)
+}
+
+fn status_key(user_id: &str) -> String {
+    format!("realtime_hub:status:{}", user_id)
+}
+
+fn last_seen_key(user_id: &str) -> String {
+    format!("realtime_hub:last_seen:{}", user_id)
+}
+
+#[async_trait]
+impl SessionStore for RedisSessions {
+    async fn add_session(&self, user_id: &str, session_id: &str) -> BusResult<usize> {
+        let now = now_millis();
+        let key = sessions_key(user_id);
+        let (count,): (usize,) = redis::pipe()
+            .atomic()
+            .zrembyscore(&key, "-inf", now)
+            .ignore()
+            .zadd(&key, session_id, now + self.ttl_millis())
+            .ignore()
+            .pexpire(&key, self.ttl_millis())
+            .ignore()
+            .zcard(&key)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(count)
+    }
+
+    async fn refresh_session(&self, user_id: &str, session_id: &str) -> BusResult<()> {
+        let key = sessions_key(user_id);
+        redis::pipe()
+            .atomic()
+            .zadd(&key, session_id, now_millis() + self.ttl_millis())
+            .ignore()
+            .pexpire(&key, self.ttl_millis())
+            .ignore()
+            .query_async::<()>(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_session(&self, user_id: &str, session_id: &str) -> BusResult<usize> {
+        let key = sessions_key(user_id);
+        let (count,): (usize,) = redis::pipe()
+            .atomic()
+            .zrem(&key, session_id)
+            .ignore()
+            .zrembyscore(&key, "-inf", now_millis())
+            .ignore()
+            .zcard(&key)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(count)
+    }
+
+    async fn session_count(&self, user_id: &str) -> BusResult<usize> {
+        let mut conn = self.conn.clone();
+        let count: usize = conn
+            .zcount(sessions_key(user_id), now_millis(), "+inf")
+            .await?;
+        Ok(count)
+    }
+
+    async fn status(&self, user_id: &str) -> BusResult<Option<Status>> {
+        let mut conn = self.conn.clone();
+        let status: Option<String> = conn.get(status_key(user_id)).await?;
+        Ok(status.map(|s| serde_json::from_str(&s)).transpose()?)
+    }
+
+    async fn set_status(&self, user_id: &str, status: Status) -> BusResult<()> {
+        let mut conn = self.conn.clone();
+        if status == Status::Online {
+            conn.del::<_, ()>(status_key(user_id)).await?;
+        } else {
+            conn.set::<_, _, ()>(status_key(user_id), serde_json::to_string(&status)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn last_seen(&self, user_id: &str) -> BusResult<Option<i64>> {
+        let mut conn = self.conn.clone();
+        Ok(conn.get(last_seen_key(user_id)).await?)
+    }
+
+    async fn mark_seen(&self, user_id: &str, at: i64) -> BusResult<()> {
+        let mut conn = self.conn.clone();
+        conn.set::<_, _, ()>(last_seen_key(user_id), at).await?;
+        Ok(())
+    }
+}
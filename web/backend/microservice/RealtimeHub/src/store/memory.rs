@@ -0,0 +1,572 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use crate::ws::now_millis;
+
+use super::{
+    CallQuery, CallRecord, CallStatus, CallStore, ChatMessage, Conference, ConferenceJoin,
+    Conversation, Cursor, DeviceKeys, Group, GroupStore, KeyStore, MessageQuery, MessageStore,
+    Participant, Prekey, ReadReceipt, RoomStore, StoreResult,
+};
+
+/// Process-local store used by tests and by local development without MongoDB.
+/// Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    messages: RwLock<Vec<ChatMessage>>,
+    calls: RwLock<Vec<CallRecord>>,
+    conferences: RwLock<Vec<Conference>>,
+    /// User id -> who holds their line, and until when
+    lines: RwLock<HashMap<String, (String, i64)>>,
+    /// User id -> when they last looked at their missed calls
+    calls_seen: RwLock<HashMap<String, i64>>,
+    /// (user id, device id) -> delivery cursor
+    deliveries: RwLock<HashMap<(String, String), Cursor>>,
+    /// Conversation key -> read receipts
+    reads: RwLock<HashMap<String, Vec<ReadReceipt>>>,
+    device_keys: RwLock<Vec<DeviceKeys>>,
+    /// Group id -> users in the room
+    rooms: RwLock<HashMap<String, HashSet<String>>>,
+    /// Stand-in for the Social service's groups.
+    groups: RwLock<HashMap<String, Group>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a group.
+    pub fn put_group(&self, group: Group) {
+        self.groups
+            .write()
+            .unwrap()
+            .insert(group.group_id.clone(), group);
+    }
+}
+
+/// Newest first, at most `limit` items.
+fn newest<T: Clone>(
+    items: impl Iterator<Item = T>,
+    timestamp: impl Fn(&T) -> i64,
+    limit: i64,
+) -> Vec<T> {
+    let mut items: Vec<T> = items.collect();
+    // Stable sort keeps insertion order for equal timestamps, so the latest insert comes first.
+    items.sort_by_key(|item| timestamp(item));
+    items.reverse();
+    items.truncate(limit.max(0) as usize);
+    items
+}
+
+#[async_trait]
+impl MessageStore for MemoryStore {
+    async fn insert_message(&self, message: &ChatMessage) -> StoreResult<Option<ChatMessage>> {
+        let mut messages = self.messages.write().unwrap();
+        if let Some(client_msg_id) = &message.client_msg_id {
+            if let Some(stored) = messages.iter().find(|m| {
+                m.sender_id == message.sender_id && m.client_msg_id.as_ref() == Some(client_msg_id)
+            }) {
+                return Ok(Some(stored.clone()));
+            }
+        }
+        messages.push(message.clone());
+        Ok(None)
+    }
+
+    async fn update_message(&self, message: &ChatMessage) -> StoreResult<()> {
+        let mut messages = self.messages.write().unwrap();
+        if let Some(stored) = messages
+            .iter_mut()
+            .find(|m| m.message_id == message.message_id)
+        {
+            stored.content = message.content.clone();
+            stored.attachments = message.attachments.clone();
+            stored.edited_at = message.edited_at;
+            stored.edits = message.edits.clone();
+            stored.deleted_at = message.deleted_at;
+            if message.is_deleted() {
+                stored.reactions.clear();
+            }
+        }
+        Ok(())
+    }
+
+    async fn increment_reply_count(&self, root_id: &str) -> StoreResult<()> {
+        let mut messages = self.messages.write().unwrap();
+        if let Some(root) = messages.iter_mut().find(|m| m.message_id == root_id) {
+            root.reply_count += 1;
+        }
+        Ok(())
+    }
+
+    async fn find_replies(
+        &self,
+        root_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>> {
+        let messages = self.messages.read().unwrap();
+        let mut replies: Vec<ChatMessage> = messages
+            .iter()
+            .filter(|m| m.thread_root.as_deref() == Some(root_id))
+            .filter(|m| after.is_none_or(|a| &m.cursor() > a))
+            .cloned()
+            .collect();
+        replies.sort_by_key(|m| m.cursor());
+        replies.truncate(limit.max(0) as usize);
+        Ok(replies)
+    }
+
+    async fn thread_participants(&self, root_id: &str) -> StoreResult<Vec<String>> {
+        let messages = self.messages.read().unwrap();
+        let mut participants: Vec<String> = messages
+            .iter()
+            .filter(|m| m.thread_root.as_deref() == Some(root_id))
+            .map(|m| m.sender_id.clone())
+            .collect();
+        participants.sort();
+        participants.dedup();
+        Ok(participants)
+    }
+
+    async fn find_messages(&self, query: &MessageQuery) -> StoreResult<Vec<ChatMessage>> {
+        let messages = self.messages.read().unwrap();
+        let mut matching: Vec<ChatMessage> = messages
+            .iter()
+            .filter(|m| query.matches(m))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|m| m.cursor());
+
+        let limit = query.limit.max(0) as usize;
+        if query.after.is_some() {
+            matching.truncate(limit);
+        } else {
+            let skip = matching.len().saturating_sub(limit);
+            matching.drain(..skip);
+        }
+        Ok(matching)
+    }
+
+    async fn find_message(&self, message_id: &str) -> StoreResult<Option<ChatMessage>> {
+        let messages = self.messages.read().unwrap();
+        Ok(messages
+            .iter()
+            .find(|m| m.message_id == message_id)
+            .cloned())
+    }
+
+    async fn add_reaction(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> StoreResult<bool> {
+        let mut messages = self.messages.write().unwrap();
+        let Some(message) = messages.iter_mut().find(|m| m.message_id == message_id) else {
+            return Ok(false);
+        };
+        let reactors = message.reactions.entry(emoji.to_string()).or_default();
+        if reactors.iter().any(|r| r == user_id) {
+            return Ok(false);
+        }
+        reactors.push(user_id.to_string());
+        Ok(true)
+    }
+
+    async fn remove_reaction(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> StoreResult<bool> {
+        let mut messages = self.messages.write().unwrap();
+        let Some(message) = messages.iter_mut().find(|m| m.message_id == message_id) else {
+            return Ok(false);
+        };
+        let Some(reactors) = message.reactions.get_mut(emoji) else {
+            return Ok(false);
+        };
+        let before = reactors.len();
+        reactors.retain(|r| r != user_id);
+        let removed = reactors.len() < before;
+        if reactors.is_empty() {
+            message.reactions.remove(emoji);
+        }
+        Ok(removed)
+    }
+
+    async fn messages_for_recipient(
+        &self,
+        user_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>> {
+        let messages = self.messages.read().unwrap();
+        let mut matching: Vec<ChatMessage> = messages
+            .iter()
+            .filter(|m| !m.is_group && m.target_id == user_id)
+            .filter(|m| after.is_none_or(|a| &m.cursor() > a))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|m| m.cursor());
+        matching.truncate(limit.max(0) as usize);
+        Ok(matching)
+    }
+
+    async fn recipient_window_start(
+        &self,
+        user_id: &str,
+        count: i64,
+    ) -> StoreResult<Option<Cursor>> {
+        let messages = self.messages.read().unwrap();
+        let mut cursors: Vec<Cursor> = messages
+            .iter()
+            .filter(|m| !m.is_group && m.target_id == user_id)
+            .map(ChatMessage::cursor)
+            .collect();
+        cursors.sort();
+        cursors.reverse();
+        Ok(cursors.into_iter().nth(count.max(0) as usize))
+    }
+
+    async fn delivery_cursor(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Cursor>> {
+        let key = (user_id.to_string(), device_id.to_string());
+        Ok(self.deliveries.read().unwrap().get(&key).cloned())
+    }
+
+    async fn advance_delivery_cursor(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()> {
+        let mut deliveries = self.deliveries.write().unwrap();
+        let current = deliveries
+            .entry((user_id.to_string(), device_id.to_string()))
+            .or_insert(cursor.clone());
+        if cursor > current {
+            *current = cursor.clone();
+        }
+        Ok(())
+    }
+
+    async fn advance_read_cursor(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()> {
+        let mut reads = self.reads.write().unwrap();
+        let receipts = reads.entry(conversation.key()).or_default();
+        let receipt = ReadReceipt {
+            user_id: user_id.to_string(),
+            message_id: cursor.message_id.clone(),
+            timestamp: cursor.timestamp,
+        };
+        match receipts.iter_mut().find(|r| r.user_id == user_id) {
+            Some(existing) if &existing.cursor() < cursor => *existing = receipt,
+            Some(_) => {}
+            None => receipts.push(receipt),
+        }
+        Ok(())
+    }
+
+    async fn read_receipts(&self, conversation: &Conversation) -> StoreResult<Vec<ReadReceipt>> {
+        let reads = self.reads.read().unwrap();
+        Ok(reads.get(&conversation.key()).cloned().unwrap_or_default())
+    }
+
+    async fn count_unread(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        after: Option<&Cursor>,
+    ) -> StoreResult<i64> {
+        let messages = self.messages.read().unwrap();
+        let count = messages
+            .iter()
+            .filter(|m| conversation.contains(m) && m.sender_id != user_id)
+            .filter(|m| after.is_none_or(|a| &m.cursor() > a))
+            .count();
+        Ok(count as i64)
+    }
+}
+
+#[async_trait]
+impl CallStore for MemoryStore {
+    async fn insert_call(&self, call: &CallRecord) -> StoreResult<()> {
+        self.calls.write().unwrap().push(call.clone());
+        Ok(())
+    }
+
+    async fn find_call(&self, call_id: &str) -> StoreResult<Option<CallRecord>> {
+        let calls = self.calls.read().unwrap();
+        Ok(calls.iter().find(|c| c.call_id == call_id).cloned())
+    }
+
+    async fn update_call(&self, call: &CallRecord, from: CallStatus) -> StoreResult<bool> {
+        let mut calls = self.calls.write().unwrap();
+        match calls
+            .iter_mut()
+            .find(|c| c.call_id == call.call_id && c.status == from)
+        {
+            Some(stored) => {
+                *stored = call.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn live_calls(&self, user_id: &str) -> StoreResult<Vec<CallRecord>> {
+        let calls = self.calls.read().unwrap();
+        Ok(calls
+            .iter()
+            .filter(|c| c.involves(user_id) && c.status.is_live())
+            .cloned()
+            .collect())
+    }
+
+    async fn claim_line(&self, user_id: &str, owner: &str, until: i64) -> StoreResult<bool> {
+        let mut lines = self.lines.write().unwrap();
+        if let Some((holder, held_until)) = lines.get(user_id) {
+            if holder != owner && *held_until >= now_millis() {
+                return Ok(false);
+            }
+        }
+        lines.insert(user_id.to_string(), (owner.to_string(), until));
+        Ok(true)
+    }
+
+    async fn release_line(&self, user_id: &str, owner: &str) -> StoreResult<()> {
+        let mut lines = self.lines.write().unwrap();
+        if lines
+            .get(user_id)
+            .is_some_and(|(holder, _)| holder == owner)
+        {
+            lines.remove(user_id);
+        }
+        Ok(())
+    }
+
+    async fn find_calls(&self, query: &CallQuery) -> StoreResult<Vec<CallRecord>> {
+        let calls = self.calls.read().unwrap();
+        let mut matching: Vec<CallRecord> =
+            calls.iter().filter(|c| query.matches(c)).cloned().collect();
+        matching.sort_by_key(|c| std::cmp::Reverse(c.cursor()));
+        matching.truncate(query.limit.max(0) as usize);
+        Ok(matching)
+    }
+
+    async fn count_missed(&self, user_id: &str, after: Option<i64>) -> StoreResult<i64> {
+        let calls = self.calls.read().unwrap();
+        let count = calls
+            .iter()
+            .filter(|c| c.is_missed_by(user_id))
+            .filter(|c| after.is_none_or(|after| c.ended_at.is_some_and(|at| at > after)))
+            .count();
+        Ok(count as i64)
+    }
+
+    async fn calls_seen_at(&self, user_id: &str) -> StoreResult<Option<i64>> {
+        Ok(self.calls_seen.read().unwrap().get(user_id).copied())
+    }
+
+    async fn mark_calls_seen(&self, user_id: &str, at: i64) -> StoreResult<()> {
+        let mut seen = self.calls_seen.write().unwrap();
+        let current = seen.entry(user_id.to_string()).or_insert(at);
+        *current = (*current).max(at);
+        Ok(())
+    }
+
+    async fn live_conference(&self, group_id: &str) -> StoreResult<Option<Conference>> {
+        let conferences = self.conferences.read().unwrap();
+        Ok(conferences
+            .iter()
+            .find(|c| c.group_id == group_id && c.ended_at.is_none())
+            .cloned())
+    }
+
+    async fn join_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+        max_participants: usize,
+    ) -> StoreResult<ConferenceJoin> {
+        let mut conferences = self.conferences.write().unwrap();
+        let index = match conferences
+            .iter()
+            .position(|c| c.group_id == group_id && c.ended_at.is_none())
+        {
+            Some(index) => index,
+            None => {
+                conferences.push(Conference {
+                    call_id: uuid::Uuid::new_v4().to_string(),
+                    group_id: group_id.to_string(),
+                    started_by: user_id.to_string(),
+                    started_at: at,
+                    active: Vec::new(),
+                    participants: Vec::new(),
+                    ended_at: None,
+                });
+                conferences.len() - 1
+            }
+        };
+        let conference = &mut conferences[index];
+        if conference.active.iter().any(|u| u == user_id) {
+            return Ok(ConferenceJoin::AlreadyIn(conference.clone()));
+        }
+        if conference.active.len() >= max_participants {
+            return Ok(ConferenceJoin::Full(conference.clone()));
+        }
+        conference.active.push(user_id.to_string());
+        conference.participants.push(Participant {
+            user_id: user_id.to_string(),
+            joined_at: at,
+            left_at: None,
+        });
+        Ok(ConferenceJoin::Joined(conference.clone()))
+    }
+
+    async fn leave_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+    ) -> StoreResult<Option<Conference>> {
+        let mut conferences = self.conferences.write().unwrap();
+        let Some(conference) = conferences.iter_mut().find(|c| {
+            c.group_id == group_id && c.ended_at.is_none() && c.active.iter().any(|u| u == user_id)
+        }) else {
+            return Ok(None);
+        };
+        conference.active.retain(|u| u != user_id);
+        for participant in &mut conference.participants {
+            if participant.user_id == user_id && participant.left_at.is_none() {
+                participant.left_at = Some(at);
+            }
+        }
+        if conference.active.is_empty() {
+            conference.ended_at = Some(at);
+        }
+        Ok(Some(conference.clone()))
+    }
+
+    async fn conferences_of(&self, user_id: &str) -> StoreResult<Vec<Conference>> {
+        let conferences = self.conferences.read().unwrap();
+        Ok(conferences
+            .iter()
+            .filter(|c| c.ended_at.is_none() && c.active.iter().any(|u| u == user_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn recent_conferences(&self, user_id: &str, limit: i64) -> StoreResult<Vec<Conference>> {
+        let conferences = self.conferences.read().unwrap();
+        let matching = conferences
+            .iter()
+            .filter(|c| c.participants.iter().any(|p| p.user_id == user_id))
+            .cloned();
+        Ok(newest(matching, |c| c.started_at, limit))
+    }
+}
+
+#[async_trait]
+impl KeyStore for MemoryStore {
+    async fn put_device_keys(&self, keys: &DeviceKeys) -> StoreResult<()> {
+        let mut devices = self.device_keys.write().unwrap();
+        devices.retain(|d| !(d.user_id == keys.user_id && d.device_id == keys.device_id));
+        devices.push(keys.clone());
+        Ok(())
+    }
+
+    async fn add_prekeys(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        prekeys: &[Prekey],
+    ) -> StoreResult<Option<usize>> {
+        let mut devices = self.device_keys.write().unwrap();
+        Ok(devices
+            .iter_mut()
+            .find(|d| d.user_id == user_id && d.device_id == device_id)
+            .map(|device| {
+                device.one_time_prekeys.extend_from_slice(prekeys);
+                device.one_time_prekeys.len()
+            }))
+    }
+
+    async fn device_keys(&self, user_id: &str) -> StoreResult<Vec<DeviceKeys>> {
+        let devices = self.device_keys.read().unwrap();
+        Ok(devices
+            .iter()
+            .filter(|d| d.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn take_prekey(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Prekey>> {
+        let mut devices = self.device_keys.write().unwrap();
+        Ok(devices
+            .iter_mut()
+            .find(|d| d.user_id == user_id && d.device_id == device_id)
+            .filter(|d| !d.one_time_prekeys.is_empty())
+            .map(|d| d.one_time_prekeys.remove(0)))
+    }
+
+    async fn remove_device(&self, user_id: &str, device_id: &str) -> StoreResult<bool> {
+        let mut devices = self.device_keys.write().unwrap();
+        let before = devices.len();
+        devices.retain(|d| !(d.user_id == user_id && d.device_id == device_id));
+        Ok(devices.len() < before)
+    }
+}
+
+#[async_trait]
+impl GroupStore for MemoryStore {
+    async fn find_group(&self, group_id: &str) -> StoreResult<Option<Group>> {
+        Ok(self.groups.read().unwrap().get(group_id).cloned())
+    }
+}
+
+#[async_trait]
+impl RoomStore for MemoryStore {
+    async fn join_room(&self, group_id: &str, user_id: &str) -> StoreResult<()> {
+        self.rooms
+            .write()
+            .unwrap()
+            .entry(group_id.to_string())
+            .or_default()
+            .insert(user_id.to_string());
+        Ok(())
+    }
+
+    async fn leave_room(&self, group_id: &str, user_id: &str) -> StoreResult<()> {
+        if let Some(members) = self.rooms.write().unwrap().get_mut(group_id) {
+            members.remove(user_id);
+        }
+        Ok(())
+    }
+
+    async fn rooms_of(&self, user_id: &str) -> StoreResult<Vec<String>> {
+        let rooms = self.rooms.read().unwrap();
+        let mut group_ids: Vec<String> = rooms
+            .iter()
+            .filter(|(_, members)| members.contains(user_id))
+            .map(|(group_id, _)| group_id.clone())
+            .collect();
+        group_ids.sort();
+        Ok(group_ids)
+    }
+
+    async fn is_in_room(&self, group_id: &str, user_id: &str) -> StoreResult<bool> {
+        let rooms = self.rooms.read().unwrap();
+        Ok(rooms
+            .get(group_id)
+            .is_some_and(|members| members.contains(user_id)))
+    }
+}
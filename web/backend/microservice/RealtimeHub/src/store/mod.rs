@@ -0,0 +1,691 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use ts_rs::TS;
+
+pub mod memory;
+pub mod mongo;
+pub mod social;
+
+pub use memory::MemoryStore;
+pub use mongo::MongoStore;
+pub use social::SocialGroups;
+
+pub type StoreResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A chat message as persisted in the `messages` collection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct ChatMessage {
+    /// Server-generated id, also the tie-breaker when timestamps collide.
+    /// Messages stored before ids existed deserialize with an empty id.
+    #[serde(default)]
+    pub message_id: String,
+    /// Id the sending client attached, if any.
+    #[serde(default)]
+    pub client_msg_id: Option<String>,
+    pub sender_id: String,
+    pub target_id: String, // UserId or GroupId
+    pub is_group: bool,
+    pub content: Option<String>,
+    pub attachments: Option<Vec<String>>,
+    pub kind: String,
+    #[ts(type = "number")]
+    pub timestamp: i64,
+    /// Message this one quotes or answers.
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// Top-level message of the thread this one is a reply in.
+    #[serde(default)]
+    pub thread_root: Option<String>,
+    /// Replies in the thread this message is the root of.
+    #[serde(default)]
+    #[ts(type = "number")]
+    pub reply_count: i64,
+    /// When the sender last edited it; `None` if never edited.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub edited_at: Option<i64>,
+    /// Earlier versions, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(as = "Option<Vec<Revision>>", optional)]
+    pub edits: Vec<Revision>,
+    /// Set once the sender deletes it. The message stays as a tombstone with
+    /// its content, attachments and edit history removed.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub deleted_at: Option<i64>,
+    /// Emoji -> users who reacted with it, in the order they reacted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[ts(as = "Option<BTreeMap<String, Vec<String>>>", optional)]
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+/// A replaced version of an edited message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct Revision {
+    pub content: Option<String>,
+    pub attachments: Option<Vec<String>>,
+    /// When this version was written.
+    #[ts(type = "number")]
+    pub timestamp: i64,
+}
+
+impl ChatMessage {
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.timestamp,
+            message_id: self.message_id.clone(),
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Moves the current version into the edit history and replaces it.
+    pub fn edit(&mut self, content: Option<String>, attachments: Option<Vec<String>>, at: i64) {
+        let previous = Revision {
+            content: std::mem::replace(&mut self.content, content),
+            attachments: std::mem::replace(&mut self.attachments, attachments),
+            timestamp: self.edited_at.unwrap_or(self.timestamp),
+        };
+        self.edits.push(previous);
+        self.edited_at = Some(at);
+    }
+
+    /// Turns the message into a tombstone.
+    pub fn delete(&mut self, at: i64) {
+        self.content = None;
+        self.attachments = None;
+        self.edits.clear();
+        self.reactions.clear();
+        self.deleted_at = Some(at);
+    }
+}
+
+/// Position in a conversation's history: `(timestamp, message_id)`. Call
+/// history uses `(started_at, call_id)` the same way.
+/// Travels over HTTP as the opaque string `"<timestamp>_<message_id>"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub timestamp: i64,
+    pub message_id: String,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.timestamp, self.message_id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (timestamp, message_id) = s
+            .split_once('_')
+            .ok_or_else(|| format!("invalid cursor: {}", s))?;
+        let timestamp = timestamp
+            .parse()
+            .map_err(|_| format!("invalid cursor timestamp: {}", s))?;
+        Ok(Cursor {
+            timestamp,
+            message_id: message_id.to_string(),
+        })
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where a call is. `ringing`, `accepted` and `connected` are live; the rest
+/// are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum CallStatus {
+    /// Offered to the callee, not answered yet.
+    Ringing,
+    /// The callee answered; media is being set up.
+    Accepted,
+    /// Media is flowing.
+    Connected,
+    /// Never answered: the ring timed out, or the caller gave up or dropped off.
+    Missed,
+    /// The callee turned it down.
+    Declined,
+    /// The callee was already in another call.
+    Busy,
+    /// Hung up after being answered. Records from before call sessions read
+    /// as ended too.
+    #[serde(other)]
+    Ended,
+}
+
+impl CallStatus {
+    pub const ALL: [CallStatus; 7] = [
+        CallStatus::Ringing,
+        CallStatus::Accepted,
+        CallStatus::Connected,
+        CallStatus::Missed,
+        CallStatus::Declined,
+        CallStatus::Busy,
+        CallStatus::Ended,
+    ];
+
+    pub fn is_live(&self) -> bool {
+        matches!(
+            self,
+            CallStatus::Ringing | CallStatus::Accepted | CallStatus::Connected
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallStatus::Ringing => "ringing",
+            CallStatus::Accepted => "accepted",
+            CallStatus::Connected => "connected",
+            CallStatus::Ended => "ended",
+            CallStatus::Missed => "missed",
+            CallStatus::Declined => "declined",
+            CallStatus::Busy => "busy",
+        }
+    }
+}
+
+impl FromStr for CallStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CallStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("unknown call status: {}", s))
+    }
+}
+
+/// One call from first ring to hang-up, as persisted in the `calls`
+/// collection. The record is written when the call starts and updated in
+/// place as it moves on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct CallRecord {
+    /// Records from before call sessions deserialize with an empty id.
+    #[serde(default)]
+    pub call_id: String,
+    pub caller_id: String,
+    pub callee_id: String,
+    #[serde(default)]
+    pub video: bool,
+    pub status: CallStatus,
+    /// When the callee's devices started ringing.
+    #[serde(alias = "timestamp")]
+    #[ts(type = "number")]
+    pub started_at: i64,
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub answered_at: Option<i64>,
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub connected_at: Option<i64>,
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub ended_at: Option<i64>,
+    /// Who hung up, declined or dropped off; `None` if the ring timed out or
+    /// the callee was busy.
+    #[serde(default)]
+    pub ended_by: Option<String>,
+}
+
+impl CallRecord {
+    pub fn involves(&self, user_id: &str) -> bool {
+        self.caller_id == user_id || self.callee_id == user_id
+    }
+
+    /// The other party, from `user_id`'s side.
+    pub fn peer_of(&self, user_id: &str) -> &str {
+        if self.caller_id == user_id {
+            &self.callee_id
+        } else {
+            &self.caller_id
+        }
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.started_at,
+            message_id: self.call_id.clone(),
+        }
+    }
+
+    /// A call to `user_id` that rang out without them picking up.
+    pub fn is_missed_by(&self, user_id: &str) -> bool {
+        self.status == CallStatus::Missed && self.callee_id == user_id
+    }
+
+    /// Time spent connected, once the call is over.
+    pub fn duration_ms(&self) -> Option<i64> {
+        Some(self.ended_at? - self.connected_at?)
+    }
+}
+
+/// A group call, as persisted in the `conferences` collection. Peers connect
+/// to each other in a mesh; the hub keeps the roster and relays signaling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct Conference {
+    pub call_id: String,
+    pub group_id: String,
+    pub started_by: String,
+    #[ts(type = "number")]
+    pub started_at: i64,
+    /// Users in the call right now, in the order they joined.
+    pub active: Vec<String>,
+    /// Everyone who took part, one entry per join.
+    pub participants: Vec<Participant>,
+    /// Set when the last participant leaves.
+    #[ts(type = "number | null")]
+    pub ended_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, TS)]
+pub struct Participant {
+    pub user_id: String,
+    #[ts(type = "number")]
+    pub joined_at: i64,
+    #[ts(type = "number | null")]
+    pub left_at: Option<i64>,
+}
+
+/// What came of asking to join a group's conference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConferenceJoin {
+    Joined(Conference),
+    /// The user was in it already, from another device.
+    AlreadyIn(Conference),
+    Full(Conference),
+}
+
+/// A one-time prekey. Each is handed out in at most one bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prekey {
+    pub key_id: u32,
+    /// Base64.
+    pub public_key: String,
+}
+
+/// A medium-term prekey, signed with the device's identity key. The hub
+/// does not check the signature; whoever fetches the bundle does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedPrekey {
+    pub key_id: u32,
+    /// Base64.
+    pub public_key: String,
+    /// Base64.
+    pub signature: String,
+}
+
+/// The public keys one device published, as persisted in the `device_keys`
+/// collection. Private keys never leave the device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceKeys {
+    pub user_id: String,
+    /// Chosen by the client; unique per user.
+    pub device_id: String,
+    /// Base64.
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// Not handed out yet, oldest first.
+    pub one_time_prekeys: Vec<Prekey>,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Privacy {
+    Public,
+    Private,
+}
+
+/// A group as the Social service models it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    pub group_id: String,
+    pub members: Vec<String>,
+    pub privacy: Privacy,
+}
+
+impl Group {
+    pub fn is_member(&self, user_id: &str) -> bool {
+        self.members.iter().any(|m| m == user_id)
+    }
+
+    /// Public groups can be listened to by anyone; private ones only by members.
+    pub fn can_join(&self, user_id: &str) -> bool {
+        self.privacy == Privacy::Public || self.is_member(user_id)
+    }
+}
+
+/// Which messages a history query should return.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conversation {
+    /// P2P: (sender=user AND target=peer) OR (sender=peer AND target=user)
+    Direct {
+        user_id: String,
+        peer_id: String,
+    },
+    Group(String),
+}
+
+impl Conversation {
+    /// The conversation `user_id` is in when addressing `target_id`.
+    pub fn of(user_id: &str, target_id: &str, is_group: bool) -> Self {
+        if is_group {
+            Conversation::Group(target_id.to_string())
+        } else {
+            Conversation::Direct {
+                user_id: user_id.to_string(),
+                peer_id: target_id.to_string(),
+            }
+        }
+    }
+
+    pub fn contains(&self, message: &ChatMessage) -> bool {
+        match self {
+            Conversation::Direct { user_id, peer_id } => {
+                !message.is_group
+                    && ((&message.sender_id == user_id && &message.target_id == peer_id)
+                        || (&message.sender_id == peer_id && &message.target_id == user_id))
+            }
+            Conversation::Group(group_id) => message.is_group && &message.target_id == group_id,
+        }
+    }
+
+    /// Perspective-free key: both participants of a DM get the same one.
+    pub fn key(&self) -> String {
+        match self {
+            Conversation::Direct { user_id, peer_id } => {
+                let (a, b) = if user_id <= peer_id {
+                    (user_id, peer_id)
+                } else {
+                    (peer_id, user_id)
+                };
+                format!("dm:{}:{}", a, b)
+            }
+            Conversation::Group(group_id) => format!("group:{}", group_id),
+        }
+    }
+}
+
+/// How far one user has read in one conversation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadReceipt {
+    pub user_id: String,
+    pub message_id: String,
+    pub timestamp: i64,
+}
+
+impl ReadReceipt {
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.timestamp,
+            message_id: self.message_id.clone(),
+        }
+    }
+}
+
+/// A window of history. Without `after` the window is anchored at the newest
+/// end (or just before `before`); with `after` it starts right after that cursor.
+/// Both bounds are exclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageQuery {
+    pub conversation: Conversation,
+    pub before: Option<Cursor>,
+    pub after: Option<Cursor>,
+    pub limit: i64,
+}
+
+impl MessageQuery {
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        let cursor = message.cursor();
+        self.conversation.contains(message)
+            && self.before.as_ref().is_none_or(|b| &cursor < b)
+            && self.after.as_ref().is_none_or(|a| &cursor > a)
+    }
+}
+
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+    /// Stores a new message. If its sender already stored one with the same
+    /// `client_msg_id`, nothing is written and that one is returned instead,
+    /// whichever node the first copy came through.
+    async fn insert_message(&self, message: &ChatMessage) -> StoreResult<Option<ChatMessage>>;
+
+    /// Saves an edit or delete of the message with the same `message_id`:
+    /// its content, attachments, edit history, deletion time and, once
+    /// deleted, its cleared reactions. Other fields are left alone.
+    async fn update_message(&self, message: &ChatMessage) -> StoreResult<()>;
+
+    /// Counts one more reply in the thread rooted at `root_id`.
+    async fn increment_reply_count(&self, root_id: &str) -> StoreResult<()>;
+
+    /// Replies in the thread, oldest first, after `after`, at most `limit`.
+    async fn find_replies(
+        &self,
+        root_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>>;
+
+    /// Everyone who replied in the thread.
+    async fn thread_participants(&self, root_id: &str) -> StoreResult<Vec<String>>;
+
+    /// At most `query.limit` messages in chronological order,
+    /// ordered by `(timestamp, message_id)`.
+    async fn find_messages(&self, query: &MessageQuery) -> StoreResult<Vec<ChatMessage>>;
+
+    async fn find_message(&self, message_id: &str) -> StoreResult<Option<ChatMessage>>;
+
+    /// Adds the user to the emoji's reactors. Returns `false` if they already were.
+    async fn add_reaction(&self, message_id: &str, emoji: &str, user_id: &str)
+        -> StoreResult<bool>;
+
+    /// Removes the user from the emoji's reactors. Returns `false` if they were not one.
+    async fn remove_reaction(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> StoreResult<bool>;
+
+    /// Oldest-first messages addressed to the user after `after`, at most `limit`.
+    /// This is what a reconnecting socket has to be caught up on.
+    async fn messages_for_recipient(
+        &self,
+        user_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>>;
+
+    /// Cursor just before the newest `count` messages addressed to the user,
+    /// or `None` if there are no more than that. A device with no delivery
+    /// cursor yet is caught up from here.
+    async fn recipient_window_start(
+        &self,
+        user_id: &str,
+        count: i64,
+    ) -> StoreResult<Option<Cursor>>;
+
+    /// Last message the user's device acknowledged as delivered.
+    async fn delivery_cursor(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Cursor>>;
+
+    /// Moves the device's delivery cursor forward; an older cursor is ignored.
+    async fn advance_delivery_cursor(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()>;
+
+    /// Moves the user's read cursor in the conversation forward; an older one is ignored.
+    async fn advance_read_cursor(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()>;
+
+    /// Every participant's read position in the conversation.
+    async fn read_receipts(&self, conversation: &Conversation) -> StoreResult<Vec<ReadReceipt>>;
+
+    /// Messages from others in the conversation newer than `after`.
+    async fn count_unread(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        after: Option<&Cursor>,
+    ) -> StoreResult<i64>;
+}
+
+/// A page of one user's calls, newest first, ordered by `(started_at, call_id)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallQuery {
+    pub user_id: String,
+    /// Only calls in one of these statuses; any status if empty.
+    pub statuses: Vec<CallStatus>,
+    /// Only calls that started at or after this time.
+    pub since: Option<i64>,
+    /// Only calls that started before this time.
+    pub until: Option<i64>,
+    /// Only calls older than this cursor (the next page).
+    pub before: Option<Cursor>,
+    pub limit: i64,
+}
+
+impl CallQuery {
+    pub fn matches(&self, call: &CallRecord) -> bool {
+        call.involves(&self.user_id)
+            && (self.statuses.is_empty() || self.statuses.contains(&call.status))
+            && self.since.is_none_or(|since| call.started_at >= since)
+            && self.until.is_none_or(|until| call.started_at < until)
+            && self.before.as_ref().is_none_or(|b| &call.cursor() < b)
+    }
+}
+
+#[async_trait]
+pub trait CallStore: Send + Sync {
+    async fn insert_call(&self, call: &CallRecord) -> StoreResult<()>;
+
+    async fn find_call(&self, call_id: &str) -> StoreResult<Option<CallRecord>>;
+
+    /// Replaces the call if it is still `from`. Returns `false` if it moved on
+    /// already, so two nodes racing on the same call cannot both advance it.
+    async fn update_call(&self, call: &CallRecord, from: CallStatus) -> StoreResult<bool>;
+
+    /// The user's ringing, accepted and connected calls.
+    async fn live_calls(&self, user_id: &str) -> StoreResult<Vec<CallRecord>>;
+
+    /// Claims the user's line for `owner` until `until` (ms since the epoch),
+    /// unless someone else holds it and has not let it expire. An invite holds
+    /// both parties' lines while it checks their calls and records its own.
+    async fn claim_line(&self, user_id: &str, owner: &str, until: i64) -> StoreResult<bool>;
+
+    /// Frees the user's line if `owner` still holds it.
+    async fn release_line(&self, user_id: &str, owner: &str) -> StoreResult<()>;
+
+    /// The group's conference that has not ended, if any.
+    async fn live_conference(&self, group_id: &str) -> StoreResult<Option<Conference>>;
+
+    /// Adds the user to the group's conference, starting one if none is live,
+    /// unless it already has `max_participants`.
+    async fn join_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+        max_participants: usize,
+    ) -> StoreResult<ConferenceJoin>;
+
+    /// Takes the user out of the group's conference; the last one out ends it.
+    /// `None` if the user was not in it.
+    async fn leave_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+    ) -> StoreResult<Option<Conference>>;
+
+    /// Live conferences the user is in.
+    async fn conferences_of(&self, user_id: &str) -> StoreResult<Vec<Conference>>;
+
+    /// Newest `limit` conferences the user took part in, newest first.
+    async fn recent_conferences(&self, user_id: &str, limit: i64) -> StoreResult<Vec<Conference>>;
+
+    /// At most `query.limit` of the user's calls, newest first.
+    async fn find_calls(&self, query: &CallQuery) -> StoreResult<Vec<CallRecord>>;
+
+    /// Calls to the user that were missed after `after`, by when they ended.
+    async fn count_missed(&self, user_id: &str, after: Option<i64>) -> StoreResult<i64>;
+
+    /// When the user last looked at their missed calls.
+    async fn calls_seen_at(&self, user_id: &str) -> StoreResult<Option<i64>>;
+
+    /// Moves the user's seen mark forward; an older time is ignored.
+    async fn mark_calls_seen(&self, user_id: &str, at: i64) -> StoreResult<()>;
+}
+
+/// Directory of the public keys devices publish for end-to-end encryption.
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// Publishes a device's keys, replacing whatever it published before.
+    async fn put_device_keys(&self, keys: &DeviceKeys) -> StoreResult<()>;
+
+    /// Appends one-time prekeys to the device's. Returns how many it has
+    /// now, or `None` if the device never published keys.
+    async fn add_prekeys(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        prekeys: &[Prekey],
+    ) -> StoreResult<Option<usize>>;
+
+    /// Keys of every device of the user.
+    async fn device_keys(&self, user_id: &str) -> StoreResult<Vec<DeviceKeys>>;
+
+    /// Removes and returns the device's oldest one-time prekey, so no two
+    /// bundles share one. `None` once they ran out.
+    async fn take_prekey(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Prekey>>;
+
+    /// Returns `false` if the device had no keys.
+    async fn remove_device(&self, user_id: &str, device_id: &str) -> StoreResult<bool>;
+}
+
+/// Read-only view of the groups the Social service owns.
+#[async_trait]
+pub trait GroupStore: Send + Sync {
+    async fn find_group(&self, group_id: &str) -> StoreResult<Option<Group>>;
+}
+
+/// Durable room membership: the groups a user has joined in the hub and is
+/// put back into on every connect, until they leave.
+#[async_trait]
+pub trait RoomStore: Send + Sync {
+    /// Joining twice is a no-op.
+    async fn join_room(&self, group_id: &str, user_id: &str) -> StoreResult<()>;
+
+    async fn leave_room(&self, group_id: &str, user_id: &str) -> StoreResult<()>;
+
+    async fn rooms_of(&self, user_id: &str) -> StoreResult<Vec<String>>;
+
+    async fn is_in_room(&self, group_id: &str, user_id: &str) -> StoreResult<bool>;
+}
@@ -0,0 +1,808 @@
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, to_bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{
+    FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions,
+    ReturnDocument, UpdateOptions,
+};
+use mongodb::{Collection, Database, IndexModel};
+
+use crate::ws::now_millis;
+
+use super::{
+    CallQuery, CallRecord, CallStatus, CallStore, ChatMessage, Conference, ConferenceJoin,
+    Conversation, Cursor, DeviceKeys, KeyStore, MessageQuery, MessageStore, Participant, Prekey,
+    ReadReceipt, RoomStore, StoreResult,
+};
+
+pub struct MongoStore {
+    db: Database,
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> Self {
+        MongoStore { db }
+    }
+
+    /// Creates the indexes the store relies on. Safe to run on every start;
+    /// existing indexes are left as they are. The unique ones are what keeps
+    /// nodes racing on the same write from storing it twice.
+    pub async fn ensure_indexes(&self) -> StoreResult<()> {
+        // A resend must not be stored twice, even when it reaches another node.
+        let resend = doc! { "client_msg_id": { "$type": "string" } };
+        self.messages()
+            .create_index(
+                unique(doc! { "sender_id": 1, "client_msg_id": 1 }, resend),
+                None,
+            )
+            .await?;
+        self.messages()
+            .create_index(unique(doc! { "message_id": 1 }, None), None)
+            .await?;
+        // One live conference per group; ended ones are kept as history.
+        let live = doc! { "ended_at": { "$type": "null" } };
+        self.conferences()
+            .create_index(unique(doc! { "group_id": 1 }, live), None)
+            .await?;
+        self.keys()
+            .create_index(unique(doc! { "user_id": 1, "device_id": 1 }, None), None)
+            .await?;
+        self.deliveries()
+            .create_index(unique(doc! { "user_id": 1, "device_id": 1 }, None), None)
+            .await?;
+        self.read_cursors()
+            .create_index(unique(doc! { "conversation": 1, "user_id": 1 }, None), None)
+            .await?;
+        self.rooms()
+            .create_index(unique(doc! { "group_id": 1, "user_id": 1 }, None), None)
+            .await?;
+        Ok(())
+    }
+
+    fn messages(&self) -> Collection<ChatMessage> {
+        self.db.collection("messages")
+    }
+
+    fn calls(&self) -> Collection<CallRecord> {
+        self.db.collection("calls")
+    }
+
+    fn conferences(&self) -> Collection<Conference> {
+        self.db.collection("conferences")
+    }
+
+    fn call_lines(&self) -> Collection<Document> {
+        self.db.collection("call_lines")
+    }
+
+    fn calls_seen(&self) -> Collection<Document> {
+        self.db.collection("calls_seen")
+    }
+
+    fn keys(&self) -> Collection<DeviceKeys> {
+        self.db.collection("device_keys")
+    }
+
+    fn deliveries(&self) -> Collection<Document> {
+        self.db.collection("deliveries")
+    }
+
+    fn read_cursors(&self) -> Collection<Document> {
+        self.db.collection("read_cursors")
+    }
+
+    fn rooms(&self) -> Collection<Document> {
+        self.db.collection("rooms")
+    }
+}
+
+/// A unique index on `keys`, over the documents matching `partial` only if
+/// one is given.
+fn unique(keys: Document, partial: impl Into<Option<Document>>) -> IndexModel {
+    let options = IndexOptions::builder()
+        .unique(true)
+        .partial_filter_expression(partial.into())
+        .build();
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+/// Whether the write was refused by a unique index.
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
+
+fn conversation_filter(conversation: &Conversation) -> Document {
+    match conversation {
+        Conversation::Group(group_id) => doc! { "is_group": true, "target_id": group_id },
+        Conversation::Direct { user_id, peer_id } => doc! {
+            "is_group": false,
+            "$or": [
+                { "sender_id": user_id, "target_id": peer_id },
+                { "sender_id": peer_id, "target_id": user_id }
+            ]
+        },
+    }
+}
+
+/// `(timestamp, message_id) <op> cursor`, with `op` being `$lt` or `$gt`.
+fn cursor_filter(op: &str, cursor: &Cursor) -> Document {
+    doc! {
+        "$or": [
+            { "timestamp": { op: cursor.timestamp } },
+            { "timestamp": cursor.timestamp, "message_id": { op: &cursor.message_id } }
+        ]
+    }
+}
+
+/// Update pipeline moving a stored `(timestamp, message_id)` up to `cursor`,
+/// leaving it alone when it is already there or past it. A single update, so
+/// concurrent acks can't move it backwards.
+fn advance_to(cursor: &Cursor) -> Vec<Document> {
+    let newer = doc! {
+        "$or": [
+            { "$lt": ["$timestamp", cursor.timestamp] },
+            { "$and": [
+                { "$eq": ["$timestamp", cursor.timestamp] },
+                { "$lt": ["$message_id", &cursor.message_id] }
+            ] }
+        ]
+    };
+    vec![doc! {
+        "$set": {
+            "timestamp": { "$cond": [newer.clone(), cursor.timestamp, "$timestamp"] },
+            "message_id": { "$cond": [newer, &cursor.message_id, "$message_id"] },
+        }
+    }]
+}
+
+fn message_filter(query: &MessageQuery) -> Document {
+    let mut clauses = vec![conversation_filter(&query.conversation)];
+    if let Some(before) = &query.before {
+        clauses.push(cursor_filter("$lt", before));
+    }
+    if let Some(after) = &query.after {
+        clauses.push(cursor_filter("$gt", after));
+    }
+    doc! { "$and": clauses }
+}
+
+fn call_filter(query: &CallQuery) -> StoreResult<Document> {
+    let mut clauses = vec![doc! {
+        "$or": [
+            { "caller_id": &query.user_id },
+            { "callee_id": &query.user_id }
+        ]
+    }];
+    if !query.statuses.is_empty() {
+        clauses.push(doc! { "status": { "$in": to_bson(&query.statuses)? } });
+    }
+    if let Some(since) = query.since {
+        clauses.push(doc! { "started_at": { "$gte": since } });
+    }
+    if let Some(until) = query.until {
+        clauses.push(doc! { "started_at": { "$lt": until } });
+    }
+    if let Some(before) = &query.before {
+        clauses.push(doc! {
+            "$or": [
+                { "started_at": { "$lt": before.timestamp } },
+                { "started_at": before.timestamp, "call_id": { "$lt": &before.message_id } }
+            ]
+        });
+    }
+    Ok(doc! { "$and": clauses })
+}
+
+#[async_trait]
+impl MessageStore for MongoStore {
+    async fn insert_message(&self, message: &ChatMessage) -> StoreResult<Option<ChatMessage>> {
+        match self.messages().insert_one(message, None).await {
+            Ok(_) => Ok(None),
+            // Only `(sender_id, client_msg_id)` can clash; message ids are fresh uuids.
+            Err(e) if is_duplicate_key(&e) => {
+                let filter = doc! {
+                    "sender_id": &message.sender_id,
+                    "client_msg_id": &message.client_msg_id
+                };
+                match self.messages().find_one(filter, None).await? {
+                    Some(stored) => Ok(Some(stored)),
+                    None => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn update_message(&self, message: &ChatMessage) -> StoreResult<()> {
+        // Only the fields an edit or delete touches, so counters and reactions
+        // written in the meantime survive.
+        let mut update = doc! {
+            "$set": {
+                "content": &message.content,
+                "attachments": &message.attachments,
+                "edited_at": message.edited_at,
+                "edits": to_bson(&message.edits)?,
+                "deleted_at": message.deleted_at,
+            }
+        };
+        if message.is_deleted() {
+            update.insert("$unset", doc! { "reactions": "" });
+        }
+        self.messages()
+            .update_one(doc! { "message_id": &message.message_id }, update, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn increment_reply_count(&self, root_id: &str) -> StoreResult<()> {
+        self.messages()
+            .update_one(
+                doc! { "message_id": root_id },
+                doc! { "$inc": { "reply_count": 1 } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn find_replies(
+        &self,
+        root_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>> {
+        let mut filter = doc! { "thread_root": root_id };
+        if let Some(after) = after {
+            filter = doc! { "$and": [filter, cursor_filter("$gt", after)] };
+        }
+        let find_options = FindOptions::builder()
+            .sort(doc! { "timestamp": 1, "message_id": 1 })
+            .limit(limit)
+            .build();
+
+        let cursor = self.messages().find(filter, find_options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn thread_participants(&self, root_id: &str) -> StoreResult<Vec<String>> {
+        let senders = self
+            .messages()
+            .distinct("sender_id", doc! { "thread_root": root_id }, None)
+            .await?;
+        Ok(senders
+            .into_iter()
+            .filter_map(|s| s.as_str().map(str::to_string))
+            .collect())
+    }
+
+    async fn find_messages(&self, query: &MessageQuery) -> StoreResult<Vec<ChatMessage>> {
+        // Walk forward from `after`, otherwise backward from `before`/the newest message.
+        let direction = if query.after.is_some() { 1 } else { -1 };
+        let find_options = FindOptions::builder()
+            .sort(doc! { "timestamp": direction, "message_id": direction })
+            .limit(query.limit)
+            .build();
+
+        let cursor = self
+            .messages()
+            .find(message_filter(query), find_options)
+            .await?;
+        let mut messages: Vec<ChatMessage> = cursor.try_collect().await?;
+        if direction == -1 {
+            messages.reverse();
+        }
+        Ok(messages)
+    }
+
+    async fn find_message(&self, message_id: &str) -> StoreResult<Option<ChatMessage>> {
+        Ok(self
+            .messages()
+            .find_one(doc! { "message_id": message_id }, None)
+            .await?)
+    }
+
+    async fn add_reaction(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> StoreResult<bool> {
+        let field = format!("reactions.{}", emoji);
+        let result = self
+            .messages()
+            .update_one(
+                doc! { "message_id": message_id },
+                doc! { "$addToSet": { field: user_id } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn remove_reaction(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> StoreResult<bool> {
+        let field = format!("reactions.{}", emoji);
+        let result = self
+            .messages()
+            .update_one(
+                doc! { "message_id": message_id },
+                doc! { "$pull": { &field: user_id } },
+                None,
+            )
+            .await?;
+        // Don't leave empty reactor lists behind.
+        self.messages()
+            .update_one(
+                doc! { "message_id": message_id, &field: { "$size": 0 } },
+                doc! { "$unset": { &field: "" } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn messages_for_recipient(
+        &self,
+        user_id: &str,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> StoreResult<Vec<ChatMessage>> {
+        let mut filter = doc! { "is_group": false, "target_id": user_id };
+        if let Some(after) = after {
+            filter = doc! { "$and": [filter, cursor_filter("$gt", after)] };
+        }
+        let find_options = FindOptions::builder()
+            .sort(doc! { "timestamp": 1, "message_id": 1 })
+            .limit(limit)
+            .build();
+
+        let cursor = self.messages().find(filter, find_options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn recipient_window_start(
+        &self,
+        user_id: &str,
+        count: i64,
+    ) -> StoreResult<Option<Cursor>> {
+        let find_options = FindOneOptions::builder()
+            .sort(doc! { "timestamp": -1, "message_id": -1 })
+            .skip(count.max(0) as u64)
+            .projection(doc! { "timestamp": 1, "message_id": 1 })
+            .build();
+        let found = self
+            .messages()
+            .clone_with_type::<Document>()
+            .find_one(
+                doc! { "is_group": false, "target_id": user_id },
+                find_options,
+            )
+            .await?;
+        Ok(match found {
+            Some(d) => Some(Cursor {
+                timestamp: d.get_i64("timestamp")?,
+                message_id: d.get_str("message_id")?.to_string(),
+            }),
+            None => None,
+        })
+    }
+
+    async fn delivery_cursor(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Cursor>> {
+        let found = self
+            .deliveries()
+            .find_one(doc! { "user_id": user_id, "device_id": device_id }, None)
+            .await?;
+        Ok(match found {
+            Some(d) => Some(Cursor {
+                timestamp: d.get_i64("timestamp")?,
+                message_id: d.get_str("message_id")?.to_string(),
+            }),
+            None => None,
+        })
+    }
+
+    async fn advance_delivery_cursor(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()> {
+        self.deliveries()
+            .update_one(
+                doc! { "user_id": user_id, "device_id": device_id },
+                advance_to(cursor),
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn advance_read_cursor(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        cursor: &Cursor,
+    ) -> StoreResult<()> {
+        self.read_cursors()
+            .update_one(
+                doc! { "conversation": conversation.key(), "user_id": user_id },
+                advance_to(cursor),
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn read_receipts(&self, conversation: &Conversation) -> StoreResult<Vec<ReadReceipt>> {
+        let cursor = self
+            .read_cursors()
+            .clone_with_type::<ReadReceipt>()
+            .find(doc! { "conversation": conversation.key() }, None)
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn count_unread(
+        &self,
+        conversation: &Conversation,
+        user_id: &str,
+        after: Option<&Cursor>,
+    ) -> StoreResult<i64> {
+        let mut clauses = vec![
+            conversation_filter(conversation),
+            doc! { "sender_id": { "$ne": user_id } },
+        ];
+        if let Some(after) = after {
+            clauses.push(cursor_filter("$gt", after));
+        }
+        let count = self
+            .messages()
+            .count_documents(doc! { "$and": clauses }, None)
+            .await?;
+        Ok(count as i64)
+    }
+}
+
+#[async_trait]
+impl CallStore for MongoStore {
+    async fn insert_call(&self, call: &CallRecord) -> StoreResult<()> {
+        self.calls().insert_one(call, None).await?;
+        Ok(())
+    }
+
+    async fn find_call(&self, call_id: &str) -> StoreResult<Option<CallRecord>> {
+        Ok(self
+            .calls()
+            .find_one(doc! { "call_id": call_id }, None)
+            .await?)
+    }
+
+    async fn update_call(&self, call: &CallRecord, from: CallStatus) -> StoreResult<bool> {
+        let filter = doc! { "call_id": &call.call_id, "status": to_bson(&from)? };
+        let result = self.calls().replace_one(filter, call, None).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn live_calls(&self, user_id: &str) -> StoreResult<Vec<CallRecord>> {
+        let live = [
+            CallStatus::Ringing,
+            CallStatus::Accepted,
+            CallStatus::Connected,
+        ];
+        let filter = doc! {
+            "$or": [
+                { "caller_id": user_id },
+                { "callee_id": user_id }
+            ],
+            "status": { "$in": to_bson(&live)? }
+        };
+        let cursor = self.calls().find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn claim_line(&self, user_id: &str, owner: &str, until: i64) -> StoreResult<bool> {
+        // Matches a line that is free or ours already; a line held by someone
+        // else makes the upsert clash with it on `_id`.
+        let claimable = doc! {
+            "_id": user_id,
+            "$or": [
+                { "owner": owner },
+                { "until": { "$lt": now_millis() } }
+            ]
+        };
+        let result = self
+            .call_lines()
+            .update_one(
+                claimable,
+                doc! { "$set": { "owner": owner, "until": until } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn release_line(&self, user_id: &str, owner: &str) -> StoreResult<()> {
+        self.call_lines()
+            .delete_one(doc! { "_id": user_id, "owner": owner }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_calls(&self, query: &CallQuery) -> StoreResult<Vec<CallRecord>> {
+        let find_options = FindOptions::builder()
+            .sort(doc! { "started_at": -1, "call_id": -1 })
+            .limit(query.limit)
+            .build();
+
+        let cursor = self.calls().find(call_filter(query)?, find_options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn count_missed(&self, user_id: &str, after: Option<i64>) -> StoreResult<i64> {
+        let mut filter = doc! { "callee_id": user_id, "status": to_bson(&CallStatus::Missed)? };
+        if let Some(after) = after {
+            filter.insert("ended_at", doc! { "$gt": after });
+        }
+        let count = self.calls().count_documents(filter, None).await?;
+        Ok(count as i64)
+    }
+
+    async fn calls_seen_at(&self, user_id: &str) -> StoreResult<Option<i64>> {
+        let seen = self
+            .calls_seen()
+            .find_one(doc! { "user_id": user_id }, None)
+            .await?;
+        Ok(seen.map(|d| d.get_i64("seen_at")).transpose()?)
+    }
+
+    async fn mark_calls_seen(&self, user_id: &str, at: i64) -> StoreResult<()> {
+        self.calls_seen()
+            .update_one(
+                doc! { "user_id": user_id },
+                doc! { "$max": { "seen_at": at } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn live_conference(&self, group_id: &str) -> StoreResult<Option<Conference>> {
+        let filter = doc! { "group_id": group_id, "ended_at": null };
+        Ok(self.conferences().find_one(filter, None).await?)
+    }
+
+    async fn join_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+        max_participants: usize,
+    ) -> StoreResult<ConferenceJoin> {
+        let live = doc! { "group_id": group_id, "ended_at": null };
+        let started = self
+            .conferences()
+            .update_one(
+                live.clone(),
+                doc! { "$setOnInsert": {
+                    "call_id": uuid::Uuid::new_v4().to_string(),
+                    "started_by": user_id,
+                    "started_at": at,
+                    "active": [],
+                    "participants": []
+                } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await;
+        match started {
+            Ok(_) => {}
+            // Someone on another node started it first; join theirs.
+            Err(e) if is_duplicate_key(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        // Only matches while the user is out and there is a free slot.
+        let mut filter = live.clone();
+        filter.insert("active", doc! { "$ne": user_id });
+        filter.insert(
+            format!("active.{}", max_participants.max(1) - 1),
+            doc! { "$exists": false },
+        );
+        let participant = Participant {
+            user_id: user_id.to_string(),
+            joined_at: at,
+            left_at: None,
+        };
+        let update = doc! {
+            "$push": { "active": user_id, "participants": to_bson(&participant)? }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        if let Some(conference) = self
+            .conferences()
+            .find_one_and_update(filter, update, options)
+            .await?
+        {
+            return Ok(ConferenceJoin::Joined(conference));
+        }
+        match self.conferences().find_one(live, None).await? {
+            Some(conference) if conference.active.iter().any(|u| u == user_id) => {
+                Ok(ConferenceJoin::AlreadyIn(conference))
+            }
+            Some(conference) => Ok(ConferenceJoin::Full(conference)),
+            None => Err(format!("conference of {} ended while joining", group_id).into()),
+        }
+    }
+
+    async fn leave_conference(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        at: i64,
+    ) -> StoreResult<Option<Conference>> {
+        let filter = doc! { "group_id": group_id, "ended_at": null, "active": user_id };
+        let update = doc! {
+            "$pull": { "active": user_id },
+            "$set": { "participants.$[p].left_at": at }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .array_filters(vec![doc! { "p.user_id": user_id, "p.left_at": null }])
+            .return_document(ReturnDocument::After)
+            .build();
+        let Some(mut conference) = self
+            .conferences()
+            .find_one_and_update(filter, update, options)
+            .await?
+        else {
+            return Ok(None);
+        };
+        if conference.active.is_empty() {
+            let last_out = doc! {
+                "call_id": &conference.call_id,
+                "active": { "$size": 0 },
+                "ended_at": null
+            };
+            let result = self
+                .conferences()
+                .update_one(last_out, doc! { "$set": { "ended_at": at } }, None)
+                .await?;
+            if result.modified_count == 1 {
+                conference.ended_at = Some(at);
+            }
+        }
+        Ok(Some(conference))
+    }
+
+    async fn conferences_of(&self, user_id: &str) -> StoreResult<Vec<Conference>> {
+        let filter = doc! { "ended_at": null, "active": user_id };
+        let cursor = self.conferences().find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn recent_conferences(&self, user_id: &str, limit: i64) -> StoreResult<Vec<Conference>> {
+        let find_options = FindOptions::builder()
+            .sort(doc! { "started_at": -1 })
+            .limit(limit)
+            .build();
+        let cursor = self
+            .conferences()
+            .find(doc! { "participants.user_id": user_id }, find_options)
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+}
+
+#[async_trait]
+impl KeyStore for MongoStore {
+    async fn put_device_keys(&self, keys: &DeviceKeys) -> StoreResult<()> {
+        self.keys()
+            .replace_one(
+                doc! { "user_id": &keys.user_id, "device_id": &keys.device_id },
+                keys,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn add_prekeys(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        prekeys: &[Prekey],
+    ) -> StoreResult<Option<usize>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let device = self
+            .keys()
+            .find_one_and_update(
+                doc! { "user_id": user_id, "device_id": device_id },
+                doc! { "$push": { "one_time_prekeys": { "$each": to_bson(prekeys)? } } },
+                options,
+            )
+            .await?;
+        Ok(device.map(|d| d.one_time_prekeys.len()))
+    }
+
+    async fn device_keys(&self, user_id: &str) -> StoreResult<Vec<DeviceKeys>> {
+        let cursor = self.keys().find(doc! { "user_id": user_id }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn take_prekey(&self, user_id: &str, device_id: &str) -> StoreResult<Option<Prekey>> {
+        // Popping in one update keeps two concurrent fetches from getting the same key.
+        let device = self
+            .keys()
+            .find_one_and_update(
+                doc! {
+                    "user_id": user_id,
+                    "device_id": device_id,
+                    "one_time_prekeys.0": { "$exists": true }
+                },
+                doc! { "$pop": { "one_time_prekeys": -1 } },
+                None,
+            )
+            .await?;
+        Ok(device.and_then(|d| d.one_time_prekeys.into_iter().next()))
+    }
+
+    async fn remove_device(&self, user_id: &str, device_id: &str) -> StoreResult<bool> {
+        let result = self
+            .keys()
+            .delete_one(doc! { "user_id": user_id, "device_id": device_id }, None)
+            .await?;
+        Ok(result.deleted_count == 1)
+    }
+}
+
+#[async_trait]
+impl RoomStore for MongoStore {
+    async fn join_room(&self, group_id: &str, user_id: &str) -> StoreResult<()> {
+        let key = doc! { "group_id": group_id, "user_id": user_id };
+        self.rooms()
+            .update_one(
+                key,
+                doc! { "$setOnInsert": { "joined_at": now_millis() } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn leave_room(&self, group_id: &str, user_id: &str) -> StoreResult<()> {
+        self.rooms()
+            .delete_one(doc! { "group_id": group_id, "user_id": user_id }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn rooms_of(&self, user_id: &str) -> StoreResult<Vec<String>> {
+        let cursor = self.rooms().find(doc! { "user_id": user_id }, None).await?;
+        let rooms: Vec<Document> = cursor.try_collect().await?;
+        Ok(rooms
+            .iter()
+            .filter_map(|room| room.get_str("group_id").ok().map(str::to_string))
+            .collect())
+    }
+
+    async fn is_in_room(&self, group_id: &str, user_id: &str) -> StoreResult<bool> {
+        let count = self
+            .rooms()
+            .count_documents(doc! { "group_id": group_id, "user_id": user_id }, None)
+            .await?;
+        Ok(count > 0)
+    }
+}
@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOneOptions;
+use mongodb::{Collection, Database};
+
+use super::{Group, GroupStore, Privacy, StoreResult};
+
+/// Groups straight from the Social service's `groups` collection, so
+/// membership changes made there apply to the next join or send.
+pub struct SocialGroups {
+    db: Database,
+}
+
+impl SocialGroups {
+    pub fn new(db: Database) -> Self {
+        SocialGroups { db }
+    }
+
+    fn groups(&self) -> Collection<Document> {
+        self.db.collection("groups")
+    }
+}
+
+#[async_trait]
+impl GroupStore for SocialGroups {
+    async fn find_group(&self, group_id: &str) -> StoreResult<Option<Group>> {
+        // Social ids are ObjectIds; anything else cannot name a group.
+        let Ok(id) = ObjectId::parse_str(group_id) else {
+            return Ok(None);
+        };
+        let find_options = FindOneOptions::builder()
+            .projection(doc! { "members": 1, "privacy": 1 })
+            .build();
+        let Some(found) = self
+            .groups()
+            .find_one(doc! { "_id": id }, find_options)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let members = match found.get_array("members") {
+            Ok(members) => members
+                .iter()
+                .filter_map(|m| m.as_str().map(str::to_string))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        // Mongoose defaults `privacy` to Public; an unexpected value is treated as Private.
+        let privacy = match found.get_str("privacy") {
+            Ok("Public") | Err(_) => Privacy::Public,
+            Ok(_) => Privacy::Private,
+        };
+        Ok(Some(Group {
+            group_id: group_id.to_string(),
+            members,
+            privacy,
+        }))
+    }
+}
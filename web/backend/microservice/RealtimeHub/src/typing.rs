@@ -0,0 +1,44 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+use crate::auth::UserId;
+
+/// Minimum gap between two relayed `typing` frames per user and conversation.
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps chatty clients from relaying a `typing` frame per keystroke.
+pub struct TypingThrottle {
+    interval: Duration,
+    last_sent: DashMap<(UserId, String), Instant>,
+}
+
+impl TypingThrottle {
+    pub fn new(interval: Duration) -> Self {
+        TypingThrottle {
+            interval,
+            last_sent: DashMap::new(),
+        }
+    }
+
+    /// Whether a typing frame for this conversation may go out now.
+    pub fn allow(&self, user_id: &UserId, conversation_key: &str) -> bool {
+        let key = (user_id.clone(), conversation_key.to_string());
+        if let Some(last_sent) = self.last_sent.get(&key) {
+            if last_sent.elapsed() < self.interval {
+                return false;
+            }
+        }
+        self.last_sent.insert(key, Instant::now());
+        true
+    }
+
+    pub fn forget(&self, user_id: &UserId) {
+        self.last_sent.retain(|(user, _), _| user != user_id);
+    }
+}
+
+impl Default for TypingThrottle {
+    fn default() -> Self {
+        Self::new(TYPING_INTERVAL)
+    }
+}
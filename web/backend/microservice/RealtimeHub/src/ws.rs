@@ -1,6 +1,6 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
         Query, State,
     },
     http::StatusCode,
@@ -8,64 +8,360 @@ use axum::{
 };
 use dashmap::{DashMap, DashSet};
 use futures::{sink::SinkExt, stream::StreamExt};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot};
 
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use mongodb::bson::doc;
-use mongodb::Database;
+use crate::auth::{self, UserId};
+use crate::bus::{Envelope, LocalBus, MessageBus, Route};
+use crate::calls::{self, CallConfig};
+use crate::conference;
+use crate::dedup::{AcceptedMessage, DedupCache};
+use crate::heartbeat::HeartbeatConfig;
+use crate::ice::IceConfig;
+use crate::keys;
+use crate::presence::{self, PresenceService, Status};
+use crate::protocol::{ClientMessage, ErrorCode, Rejection, ServerMessage, ENCRYPTED_KIND};
+use crate::ratelimit::{BucketStore, FrameKind, RateLimitConfig, RateLimiter, Verdict};
+use crate::registry::{ConnectionRegistry, Outbound};
+use crate::sessions::{LocalSessions, SessionStore};
+use crate::store::{
+    CallStore, ChatMessage, Conversation, Group, GroupStore, KeyStore, MemoryStore, MessageStore,
+    RoomStore,
+};
+use crate::typing::TypingThrottle;
 
-// UserId -> Sender
-pub type ConnectionState = Arc<DashMap<String, mpsc::UnboundedSender<Message>>>;
-// GroupId -> Set of UserIds
-pub type GroupState = Arc<DashMap<String, DashSet<String>>>;
+// UserId -> Sessions
+pub type ConnectionState = Arc<ConnectionRegistry>;
+// GroupId -> UserIds in the room with a socket on this node (who joined is in `rooms`)
+pub type GroupState = Arc<DashMap<String, DashSet<UserId>>>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub connections: ConnectionState,
     pub groups: GroupState,
-    pub db: Database,
+    pub messages: Arc<dyn MessageStore>,
+    pub calls: Arc<dyn CallStore>,
+    /// Who may be in which group; owned by the Social service.
+    pub directory: Arc<dyn GroupStore>,
+    /// Rooms users joined, restored on every connect.
+    pub rooms: Arc<dyn RoomStore>,
+    /// Public keys of every device, for end-to-end encrypted DMs.
+    pub keys: Arc<dyn KeyStore>,
+    pub dedup: Arc<DedupCache>,
+    pub presence: Arc<PresenceService>,
+    /// Who is connected on any node, for presence.
+    pub sessions: Arc<dyn SessionStore>,
+    pub typing: Arc<TypingThrottle>,
+    pub bus: Arc<dyn MessageBus>,
+    pub heartbeat: HeartbeatConfig,
+    pub call_config: CallConfig,
+    /// STUN and TURN servers handed to callers.
+    pub ice: Arc<IceConfig>,
+    pub limits: Arc<RateLimiter>,
+    /// Identifies this hub instance on the bus.
+    pub node_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub user_id: String,
-    pub email: String,
-    pub exp: usize,
-}
+impl AppState {
+    pub fn new(
+        messages: Arc<dyn MessageStore>,
+        calls: Arc<dyn CallStore>,
+        directory: Arc<dyn GroupStore>,
+        rooms: Arc<dyn RoomStore>,
+        keys: Arc<dyn KeyStore>,
+    ) -> Self {
+        AppState {
+            connections: Arc::new(ConnectionRegistry::new()),
+            groups: Arc::new(DashMap::new()),
+            messages,
+            calls,
+            directory,
+            rooms,
+            keys,
+            dedup: Arc::new(DedupCache::default()),
+            presence: Arc::new(PresenceService::new()),
+            sessions: Arc::new(LocalSessions::new()),
+            typing: Arc::new(TypingThrottle::default()),
+            bus: Arc::new(LocalBus::new()),
+            heartbeat: HeartbeatConfig::default(),
+            call_config: CallConfig::default(),
+            ice: Arc::new(IceConfig::default()),
+            limits: Arc::new(RateLimiter::default()),
+            node_id: uuid::Uuid::new_v4().to_string(),
+        }
+    }
 
-#[derive(Deserialize)]
-pub struct AuthParams {
-    pub token: String,
-}
+    /// Swaps the node-private default bus for one shared with other nodes.
+    pub fn with_bus(mut self, bus: Arc<dyn MessageBus>) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Swaps the node-private default sessions for ones shared with other nodes.
+    pub fn with_sessions(mut self, sessions: Arc<dyn SessionStore>) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn with_call_config(mut self, call_config: CallConfig) -> Self {
+        self.call_config = call_config;
+        self
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum ClientMessage {
-    #[serde(rename = "join")]
-    Join { user_id: String },
+    pub fn with_ice_config(mut self, ice: IceConfig) -> Self {
+        self.ice = Arc::new(ice);
+        self
+    }
+
+    pub fn with_rate_limits(mut self, config: RateLimitConfig) -> Self {
+        self.limits = Arc::new(RateLimiter::with_buckets(config, self.limits.buckets()));
+        self
+    }
+
+    /// Swaps the node-private default rate limit buckets for ones shared with
+    /// other nodes, so each budget holds across the cluster.
+    pub fn with_rate_buckets(mut self, buckets: Arc<dyn BucketStore>) -> Self {
+        self.limits = Arc::new(RateLimiter::with_buckets(self.limits.config(), buckets));
+        self
+    }
+
+    /// Gives every session an outbound queue of `capacity` frames. Call
+    /// before serving any socket.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.connections = Arc::new(ConnectionRegistry::with_capacity(capacity));
+        self
+    }
 
-    #[serde(rename = "join_group")]
-    JoinGroup { user_id: String, group_id: String },
+    /// Delivers a frame to the route's sockets on this node and publishes it
+    /// so every other node does the same for its sockets.
+    pub async fn deliver(&self, route: Route, frame: String) {
+        self.publish(route, frame, false).await;
+    }
 
-    #[serde(rename = "leave_group")]
-    LeaveGroup { user_id: String, group_id: String },
+    /// Like `deliver`, for frames a lagging socket may miss.
+    pub async fn deliver_ephemeral(&self, route: Route, frame: String) {
+        self.publish(route, frame, true).await;
+    }
+
+    async fn publish(&self, route: Route, frame: String, ephemeral: bool) {
+        self.deliver_local(&route, &frame, ephemeral);
+        let envelope = Envelope {
+            origin: self.node_id.clone(),
+            route,
+            frame,
+            ephemeral,
+        };
+        if let Err(e) = self.bus.publish(&envelope).await {
+            eprintln!("Failed to publish to bus: {}", e);
+        }
+    }
+
+    fn deliver_local(&self, route: &Route, frame: &str, ephemeral: bool) {
+        match route {
+            Route::User { user_id } => {
+                self.connections
+                    .send_to_user(user_id, Message::Text(frame.to_string()), ephemeral);
+            }
+            Route::Group { group_id, except } => {
+                if let Some(members) = self.groups.get(group_id) {
+                    for member_id in members.iter() {
+                        if except.as_deref() == Some(member_id.key().as_str()) {
+                            continue;
+                        }
+                        self.connections.send_to_user(
+                            member_id.key().as_str(),
+                            Message::Text(frame.to_string()),
+                            ephemeral,
+                        );
+                    }
+                }
+            }
+            Route::OutsideGroup { group_id, user_ids } => {
+                for user_id in user_ids {
+                    if self.is_group_member(group_id, &UserId::from(user_id.as_str())) {
+                        continue;
+                    }
+                    self.connections.send_to_user(
+                        user_id,
+                        Message::Text(frame.to_string()),
+                        ephemeral,
+                    );
+                }
+            }
+            Route::Watchers { user_id, group_ids } => {
+                let user_id = UserId::from(user_id.as_str());
+                for watcher in presence::audience(self, &user_id, group_ids) {
+                    self.connections.send_to_user(
+                        watcher.as_str(),
+                        Message::Text(frame.to_string()),
+                        ephemeral,
+                    );
+                }
+            }
+        }
+    }
 
-    #[serde(rename = "chat")]
-    Chat {
-        target_id: String, // UserId or GroupId
+    /// Delivers a frame to a conversation: the whole group, or the peer and
+    /// the sender's own devices for a DM.
+    pub async fn deliver_to_conversation(
+        &self,
+        sender: &UserId,
+        target_id: &str,
         is_group: bool,
-        content: Option<String>,
-        attachments: Option<Vec<String>>,
-        kind: String,
-    },
+        frame: String,
+    ) {
+        if is_group {
+            let route = Route::Group {
+                group_id: target_id.to_string(),
+                except: None,
+            };
+            self.deliver(route, frame).await;
+        } else {
+            let route = Route::User {
+                user_id: target_id.to_string(),
+            };
+            self.deliver(route, frame.clone()).await;
+            let route = Route::User {
+                user_id: sender.to_string(),
+            };
+            self.deliver(route, frame).await;
+        }
+    }
 
-    #[serde(rename = "signal")]
-    Signal {
-        target_id: String,
-        payload: serde_json::Value,
-    },
+    /// Replays frames published by other nodes to the sockets on this one.
+    pub fn spawn_bus_listener(&self) -> tokio::task::JoinHandle<()> {
+        let state = self.clone();
+        let mut rx = state.bus.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(envelope) if envelope.origin != state.node_id => {
+                        state.deliver_local(&envelope.route, &envelope.frame, envelope.ephemeral);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Bus listener lagged, {} frames dropped", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Whether the user is currently in the group's room with a socket on this node.
+    pub fn is_group_member(&self, group_id: &str, user_id: &UserId) -> bool {
+        self.groups
+            .get(group_id)
+            .is_some_and(|members| members.contains(user_id))
+    }
+
+    /// Whether the user joined the group's room, connected or not.
+    pub async fn is_in_room(&self, group_id: &str, user_id: &UserId) -> bool {
+        if self.is_group_member(group_id, user_id) {
+            return true;
+        }
+        match self.rooms.is_in_room(group_id, user_id.as_str()).await {
+            Ok(joined) => joined,
+            Err(e) => {
+                eprintln!("Failed to load rooms of {}: {}", user_id, e);
+                false
+            }
+        }
+    }
+
+    fn enter_room(&self, group_id: &str, user_id: &UserId) {
+        self.groups
+            .entry(group_id.to_string())
+            .or_default()
+            .insert(user_id.clone());
+    }
+
+    /// Puts a connecting user back into the rooms they joined earlier. Rooms
+    /// of groups they may no longer join are left for good.
+    async fn restore_rooms(&self, user_id: &UserId) {
+        let group_ids = match self.rooms.rooms_of(user_id.as_str()).await {
+            Ok(group_ids) => group_ids,
+            Err(e) => {
+                eprintln!("Failed to load rooms of {}: {}", user_id, e);
+                return;
+            }
+        };
+        for group_id in group_ids {
+            if self.can_join_group(&group_id, user_id).await {
+                self.enter_room(&group_id, user_id);
+            } else if let Err(e) = self.rooms.leave_room(&group_id, user_id.as_str()).await {
+                eprintln!("Failed to drop room {} of {}: {}", group_id, user_id, e);
+            }
+        }
+    }
+
+    /// Looks the group up in the directory. Lookup failures count as no group.
+    async fn find_group(&self, group_id: &str) -> Option<Group> {
+        match self.directory.find_group(group_id).await {
+            Ok(group) => group,
+            Err(e) => {
+                eprintln!("Failed to load group {}: {}", group_id, e);
+                None
+            }
+        }
+    }
+
+    /// Whether the user may enter the group's room and receive its traffic.
+    pub async fn can_join_group(&self, group_id: &str, user_id: &UserId) -> bool {
+        self.find_group(group_id)
+            .await
+            .is_some_and(|group| group.can_join(user_id.as_str()))
+    }
+
+    /// Whether the user may read the group's history: they joined its room
+    /// and the directory still lets them in.
+    pub async fn can_read_group(&self, group_id: &str, user_id: &UserId) -> bool {
+        self.is_in_room(group_id, user_id).await && self.can_join_group(group_id, user_id).await
+    }
+
+    /// Whether the user may send to the group. Only members can, even in public groups.
+    pub async fn can_post_to_group(&self, group_id: &str, user_id: &UserId) -> bool {
+        self.find_group(group_id)
+            .await
+            .is_some_and(|group| group.is_member(user_id.as_str()))
+    }
+
+    /// State backed by a fresh `MemoryStore`, for tests and local dev without MongoDB.
+    pub fn in_memory() -> Self {
+        Self::with_store(Arc::new(MemoryStore::new()))
+    }
+
+    /// State backed entirely by the given `MemoryStore`.
+    pub fn with_store(store: Arc<MemoryStore>) -> Self {
+        Self::new(
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store,
+        )
+    }
+}
+
+/// Device a socket counts as when the client doesn't name one.
+pub const DEFAULT_DEVICE: &str = "default";
+
+#[derive(Deserialize)]
+pub struct AuthParams {
+    pub token: String,
+    /// Stable id of the connecting device. Each device has its own delivery
+    /// cursor, so one device acknowledging a message doesn't keep it from the
+    /// others' offline replay.
+    #[serde(default)]
+    pub device_id: Option<String>,
 }
 
 pub async fn ws_handler(
@@ -73,174 +369,859 @@ pub async fn ws_handler(
     Query(params): Query<AuthParams>,
     State(state): State<AppState>,
 ) -> Result<Response, StatusCode> {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let token_data = decode::<Claims>(
-        &params.token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    )
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
-
-    let user_id = token_data.claims.user_id;
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, user_id)))
+    let user_id = auth::authenticate(&params.token)?.user_id;
+    let device_id = params
+        .device_id
+        .unwrap_or_else(|| DEFAULT_DEVICE.to_string());
+    if !keys::valid_device_id(&device_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, user_id, device_id)))
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState, user_id: String) {
+async fn handle_socket(socket: WebSocket, state: AppState, user_id: UserId, device_id: String) {
     let (mut sender, mut receiver) = socket.split();
-    let (tx, mut rx) = mpsc::unbounded_channel();
-
-    // Register user immediately
-    state.connections.insert(user_id.clone(), tx.clone());
-    println!("User {} connected (Authenticated)", user_id);
+    let (tx, mut rx) = Outbound::channel(state.connections.queue_capacity());
+    // The offline replay has a queue of its own, written out before any live
+    // frame; live frames wait in theirs meanwhile.
+    let (replay, mut replay_rx) = Outbound::channel(state.connections.queue_capacity());
+    let (replayed_tx, mut replayed_rx) = oneshot::channel::<HashSet<String>>();
 
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if sender.send(msg).await.is_err() {
-                break;
+    // Writes the queues to the socket. A frame that must not be lost finding the
+    // queue full means the client is too slow to keep up; it is disconnected,
+    // with a close frame if the socket still takes one.
+    let writer = tx.clone();
+    let mut send_task = tokio::spawn(async move {
+        let mut replaying = true;
+        let mut replayed = HashSet::new();
+        loop {
+            let msg = tokio::select! {
+                msg = replay_rx.recv(), if replaying => match msg {
+                    Some(msg) => msg,
+                    None => {
+                        replaying = false;
+                        replayed = replayed_rx.try_recv().unwrap_or_default();
+                        continue;
+                    }
+                },
+                msg = rx.recv(), if !replaying => match msg {
+                    Some(msg) if already_replayed(&msg, &mut replayed) => continue,
+                    Some(msg) => msg,
+                    None => return,
+                },
+                _ = writer.overflowed() => break,
+            };
+            let closing = matches!(msg, Message::Close(_));
+            tokio::select! {
+                sent = sender.send(msg) => {
+                    if sent.is_err() || closing {
+                        return;
+                    }
+                }
+                _ = writer.overflowed() => break,
             }
         }
+        let close = CloseFrame {
+            code: close_code::AGAIN,
+            reason: "outbound queue full".into(),
+        };
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, sender.send(Message::Close(Some(close)))).await;
     });
 
+    // Back into the joined rooms before `caught_up`, so the client can rely on
+    // them from then on. Idempotent, so every new session does it.
+    state.restore_rooms(&user_id).await;
+
+    // Register before reading the backlog, so a message stored meanwhile is
+    // either replayed or queued live; one that is both only goes out once.
+    let session_id = state.connections.register(&user_id, tx.clone());
+    println!(
+        "User {} connected (Authenticated, session {})",
+        user_id, session_id
+    );
+    let replayed = catch_up(&state, &user_id, &device_id, &replay).await;
+    let _ = replayed_tx.send(replayed);
+    drop(replay);
+
+    presence::session_opened(&state, &user_id, &session_id).await;
+
     let my_user_id = user_id.clone();
-    let mut my_groups: Vec<String> = Vec::new();
-
-    while let Some(msg) = receiver.next().await {
-        if let Ok(Message::Text(text)) = msg {
-            if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
-                match client_msg {
-                    ClientMessage::Join { user_id: _ } => {
-                        // Already joined/authenticated.
-                        // We ignore the user_id payload here to prevent spoofing,
-                        // or we could error if it doesn't match `my_user_id`.
-                        // For now, no-op or just log.
-                        // println!("Received join message for already auth user {}", my_user_id);
-                    }
-                    ClientMessage::JoinGroup { user_id, group_id } => {
-                        // Enforce user_id matches authenticated id?
-                        if user_id == my_user_id {
-                            state
-                                .groups
-                                .entry(group_id.clone())
-                                .or_insert_with(DashSet::new)
-                                .insert(user_id.clone());
-                            my_groups.push(group_id.clone());
-                            println!("User {} joined group {}", user_id, group_id);
-                        }
-                    }
-                    ClientMessage::LeaveGroup { user_id, group_id } => {
-                        if user_id == my_user_id {
-                            if let Some(members) = state.groups.get(&group_id) {
-                                members.remove(&user_id);
-                            }
-                        }
+
+    let mut heartbeat = tokio::time::interval(state.heartbeat.interval);
+    heartbeat.tick().await; // the first tick is immediate
+    let mut last_heard = Instant::now();
+
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => msg,
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > state.heartbeat.timeout {
+                    println!("Reaping unresponsive session {} of {}", session_id, my_user_id);
+                    break;
+                }
+                tx.send_ephemeral(Message::Ping(Vec::new()));
+                if let Err(e) = state.sessions.refresh_session(my_user_id.as_str(), &session_id).await {
+                    eprintln!("Failed to refresh session {} of {}: {}", session_id, my_user_id, e);
+                }
+                continue;
+            }
+            // Writing failed, so the socket is gone even if no close arrived.
+            _ = &mut send_task => break,
+        };
+        let parsed = match msg {
+            Some(Ok(Message::Text(text))) => ClientMessage::parse(&text),
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            // Pings are answered by axum itself; either way the client is alive.
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => {
+                last_heard = Instant::now();
+                continue;
+            }
+            Some(Ok(Message::Binary(_))) => Err(Rejection {
+                code: ErrorCode::Unsupported,
+                message: "binary frames are not supported".to_string(),
+                client_msg_id: None,
+            }),
+        };
+        last_heard = Instant::now();
+
+        // Frames the hub cannot use cost a control frame, so flooding it with
+        // garbage is no cheaper than with anything else.
+        let (kind, client_msg_id) = match &parsed {
+            Ok(client_msg) => (client_msg.rate_class(), client_msg.client_msg_id()),
+            Err(rejection) => (FrameKind::Control, rejection.client_msg_id.as_deref()),
+        };
+        match state.limits.check(&my_user_id, kind).await {
+            Verdict::Allow => {}
+            Verdict::Deny { retry_after } => {
+                tx.send(rate_limited_frame(kind, retry_after, client_msg_id));
+                continue;
+            }
+            Verdict::Disconnect => {
+                println!("Disconnecting {} for flooding", my_user_id);
+                let close = CloseFrame {
+                    code: close_code::POLICY,
+                    reason: "rate limit exceeded".into(),
+                };
+                tx.send(Message::Close(Some(close)));
+                let _ = tokio::time::timeout(CLOSE_TIMEOUT, &mut send_task).await;
+                break;
+            }
+        }
+        let client_msg = match parsed {
+            Ok(client_msg) => client_msg,
+            Err(rejection) => {
+                tx.send(ServerMessage::from(rejection).to_message());
+                continue;
+            }
+        };
+        if let Err(reason) = client_msg.validate() {
+            let client_msg_id = client_msg.client_msg_id().map(str::to_string);
+            let error = ServerMessage::error(ErrorCode::Invalid, reason, client_msg_id);
+            tx.send(error.to_message());
+            continue;
+        }
+        // The socket is already authenticated; a frame claiming another user is a client bug or spoofing.
+        if client_msg
+            .claimed_user_id()
+            .is_some_and(|claimed| my_user_id.as_str() != claimed)
+        {
+            let error = ServerMessage::error(
+                ErrorCode::Forbidden,
+                "user_id does not match the authenticated user",
+                None,
+            );
+            tx.send(error.to_message());
+            continue;
+        }
+        match client_msg {
+            ClientMessage::Join { .. } => {
+                // Already joined/authenticated.
+            }
+            ClientMessage::JoinGroup { user_id, group_id } => {
+                if !state.can_join_group(&group_id, &my_user_id).await {
+                    let error = ServerMessage::error(
+                        ErrorCode::Forbidden,
+                        format!("not allowed to join group {}", group_id),
+                        None,
+                    );
+                    tx.send(error.to_message());
+                } else if let Err(e) = state.rooms.join_room(&group_id, my_user_id.as_str()).await {
+                    eprintln!("Failed to save room {} of {}: {}", group_id, user_id, e);
+                } else {
+                    state.enter_room(&group_id, &my_user_id);
+                    println!("User {} joined group {}", user_id, group_id);
+                }
+            }
+            ClientMessage::LeaveGroup { user_id, group_id } => {
+                conference::leave(&state, &my_user_id, &group_id).await;
+                if let Err(e) = state.rooms.leave_room(&group_id, my_user_id.as_str()).await {
+                    eprintln!("Failed to leave room {} of {}: {}", group_id, user_id, e);
+                    continue;
+                }
+                if let Some(members) = state.groups.get(&group_id) {
+                    members.remove(&my_user_id);
+                }
+            }
+            ClientMessage::Chat {
+                target_id,
+                is_group,
+                content,
+                attachments,
+                kind,
+                client_msg_id,
+                reply_to,
+                thread_root,
+            } => {
+                let message = ChatMessage {
+                    message_id: uuid::Uuid::new_v4().to_string(),
+                    client_msg_id,
+                    sender_id: my_user_id.to_string(),
+                    target_id,
+                    is_group,
+                    content,
+                    attachments,
+                    kind,
+                    timestamp: now_millis(),
+                    reply_to,
+                    thread_root,
+                    ..Default::default()
+                };
+                handle_chat(&state, &my_user_id, &tx, message).await;
+            }
+            // Offers, answers and ICE candidates, relayed as they are. The call
+            // itself is tracked through the `call_*` frames.
+            ClientMessage::Signal { target_id, payload } => {
+                calls::relay(&state, &my_user_id, &tx, &target_id, payload).await;
+            }
+            ClientMessage::Delivered { message_id } => {
+                mark_delivered(&state, &my_user_id, &device_id, &message_id).await;
+            }
+            ClientMessage::SetStatus { status } => {
+                if status != Status::Offline {
+                    if let Err(e) = state.sessions.set_status(my_user_id.as_str(), status).await {
+                        eprintln!("Failed to save status of {}: {}", my_user_id, e);
                     }
-                    ClientMessage::Chat {
-                        target_id,
+                    presence::broadcast_presence(&state, &my_user_id).await;
+                }
+            }
+            ClientMessage::SubscribePresence { user_ids } => {
+                for user_id in user_ids.into_iter().map(UserId::from) {
+                    state.presence.subscribe(&my_user_id, &user_id);
+                    let current = presence::presence_of(&state, &user_id).await;
+                    tx.send(Message::Text(presence::presence_frame(&current)));
+                }
+            }
+            ClientMessage::UnsubscribePresence { user_ids } => {
+                for user_id in user_ids.into_iter().map(UserId::from) {
+                    state.presence.unsubscribe(&my_user_id, &user_id);
+                }
+            }
+            ClientMessage::Typing {
+                target_id,
+                is_group,
+            } => {
+                let conversation = Conversation::of(my_user_id.as_str(), &target_id, is_group);
+                if is_group && !state.is_group_member(&target_id, &my_user_id) {
+                    continue;
+                }
+                if state.typing.allow(&my_user_id, &conversation.key()) {
+                    let frame = ServerMessage::Typing {
+                        sender_id: my_user_id.to_string(),
+                        target_id: target_id.clone(),
                         is_group,
-                        content,
-                        attachments,
-                        kind,
-                    } => {
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as i64;
-
-                        let response = serde_json::json!({
-                            "type": "chat",
-                            "sender_id": my_user_id,
-                            "target_id": target_id,
-                            "is_group": is_group,
-                            "content": content,
-                            "attachments": attachments,
-                            "kind": kind,
-                            "timestamp": timestamp
-                        });
-                        let msg_str = serde_json::to_string(&response).unwrap();
-
-                        // SAVE TO DB
-                        let collection = state.db.collection::<mongodb::bson::Document>("messages");
-                        let doc = doc! {
-                            "sender_id": &my_user_id,
-                            "target_id": &target_id,
-                            "is_group": is_group,
-                            "content": &content,
-                            "attachments": &attachments,
-                            "kind": &kind,
-                            "timestamp": timestamp
-                        };
-                        let _ = collection.insert_one(doc, None).await;
-
-                        if is_group {
-                            // Broadcast to group members
-                            if let Some(members) = state.groups.get(&target_id) {
-                                for member_id in members.iter() {
-                                    if let Some(conn) = state.connections.get(member_id.key()) {
-                                        let _ = conn.send(Message::Text(msg_str.clone()));
-                                    }
-                                }
-                            }
-                        } else {
-                            // 1-on-1
-                            if let Some(target_tx) = state.connections.get(&target_id) {
-                                let _ = target_tx.send(Message::Text(msg_str.clone()));
-                            }
-                            // Echo to sender
-                            if let Some(my_tx) = state.connections.get(&my_user_id) {
-                                let _ = my_tx.send(Message::Text(msg_str));
-                            }
+                    };
+                    // Everyone in the conversation but the typist.
+                    let route = if is_group {
+                        Route::Group {
+                            group_id: target_id,
+                            except: Some(my_user_id.to_string()),
                         }
-                    }
-                    ClientMessage::Signal { target_id, payload } => {
-                        let signal_msg = serde_json::json!({
-                            "type": "signal",
-                            "sender_id": my_user_id,
-                            "payload": payload
-                        });
-                        let msg_str = serde_json::to_string(&signal_msg).unwrap();
-
-                        // CALL HISTORY: If payload indicates call end
-                        if let Some(type_str) = payload.get("type").and_then(|v| v.as_str()) {
-                            if type_str == "bye" || type_str == "end-call" || type_str == "reject" {
-                                let timestamp = std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis()
-                                    as i64;
-
-                                let calls_coll =
-                                    state.db.collection::<mongodb::bson::Document>("calls");
-                                let payload_bson = mongodb::bson::to_bson(&payload)
-                                    .unwrap_or(mongodb::bson::Bson::Null);
-
-                                let doc = doc! {
-                                    "caller_id": &my_user_id,
-                                    "callee_id": &target_id,
-                                    "status": type_str,
-                                    "timestamp": timestamp,
-                                    "payload": payload_bson
-                                };
-                                let _ = calls_coll.insert_one(doc, None).await;
-                            }
-                        }
-
-                        if let Some(target_tx) = state.connections.get(&target_id) {
-                            let _ = target_tx.send(Message::Text(msg_str));
-                        }
-                    }
+                    } else {
+                        Route::User { user_id: target_id }
+                    };
+                    state.deliver_ephemeral(route, frame.to_frame()).await;
                 }
             }
-        } else {
-            break;
+            ClientMessage::Read {
+                target_id,
+                is_group,
+                message_id,
+            } => {
+                mark_read(&state, &my_user_id, &target_id, is_group, &message_id).await;
+            }
+            ClientMessage::EditMessage {
+                message_id,
+                content,
+                attachments,
+            } => {
+                edit_message(&state, &my_user_id, &tx, &message_id, content, attachments).await;
+            }
+            ClientMessage::DeleteMessage { message_id } => {
+                delete_message(&state, &my_user_id, &tx, &message_id).await;
+            }
+            ClientMessage::React { message_id, emoji } => {
+                react(&state, &my_user_id, &tx, &message_id, &emoji, true).await;
+            }
+            ClientMessage::Unreact { message_id, emoji } => {
+                react(&state, &my_user_id, &tx, &message_id, &emoji, false).await;
+            }
+            ClientMessage::CallInvite { target_id, video } => {
+                calls::invite(&state, &my_user_id, &tx, &target_id, video).await;
+            }
+            ClientMessage::CallAccept { call_id } => {
+                calls::accept(&state, &my_user_id, &tx, &call_id).await;
+            }
+            ClientMessage::CallDecline { call_id } => {
+                calls::decline(&state, &my_user_id, &tx, &call_id).await;
+            }
+            ClientMessage::CallConnected { call_id } => {
+                calls::connected(&state, &my_user_id, &tx, &call_id).await;
+            }
+            ClientMessage::CallEnd { call_id } => {
+                calls::end(&state, &my_user_id, &tx, &call_id).await;
+            }
+            ClientMessage::ConferenceJoin { group_id } => {
+                conference::join(&state, &my_user_id, &tx, &group_id).await;
+            }
+            ClientMessage::ConferenceLeave { group_id } => {
+                conference::leave(&state, &my_user_id, &group_id).await;
+            }
+            ClientMessage::ConferenceSignal {
+                group_id,
+                target_id,
+                payload,
+            } => {
+                conference::relay(&state, &my_user_id, &tx, &group_id, &target_id, payload).await;
+            }
         }
     }
 
-    state.connections.remove(&my_user_id);
-    for gid in my_groups {
-        if let Some(members) = state.groups.get(&gid) {
+    // Presence and calls only change with the user's last session anywhere,
+    // and live room presence with their last one on this node. The joined
+    // rooms themselves stay for the next connect.
+    let last_here = state.connections.unregister(&my_user_id, &session_id);
+    let offline = presence::session_closed(&state, &my_user_id, &session_id).await;
+    if offline {
+        calls::drop_calls(&state, &my_user_id).await;
+        conference::leave_all(&state, &my_user_id).await;
+    }
+    if last_here {
+        for members in state.groups.iter() {
             members.remove(&my_user_id);
         }
+        state.presence.unsubscribe_all(&my_user_id);
+        state.typing.forget(&my_user_id);
     }
 
     send_task.abort();
 }
+
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// How long a slow consumer gets to take its close frame before the socket is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most messages replayed to one socket on connect; older ones stay in `/messages`.
+const CATCH_UP_LIMIT: i64 = 1000;
+const CATCH_UP_BATCH: i64 = 100;
+
+fn chat_frame(message: &ChatMessage) -> String {
+    ServerMessage::Chat(message.clone()).to_frame()
+}
+
+/// Replays messages addressed to the user since the device's delivery cursor,
+/// then sends a `caught_up` marker. The cursor only moves when the client
+/// answers with `delivered`, so anything not acknowledged is replayed again
+/// next time. A device without a cursor gets the newest `CATCH_UP_LIMIT`
+/// messages; older ones are in `/messages`. Returns the ids of the replayed
+/// messages.
+async fn catch_up(
+    state: &AppState,
+    user_id: &UserId,
+    device_id: &str,
+    tx: &Outbound,
+) -> HashSet<String> {
+    let mut replayed = HashSet::new();
+    let mut cursor = match state
+        .messages
+        .delivery_cursor(user_id.as_str(), device_id)
+        .await
+    {
+        Ok(Some(cursor)) => Some(cursor),
+        Ok(None) => match state
+            .messages
+            .recipient_window_start(user_id.as_str(), CATCH_UP_LIMIT)
+            .await
+        {
+            Ok(start) => start,
+            Err(e) => {
+                eprintln!("Failed to load missed messages for {}: {}", user_id, e);
+                return replayed;
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to load delivery cursor for {}: {}", user_id, e);
+            return replayed;
+        }
+    };
+
+    let mut count = 0;
+    while count < CATCH_UP_LIMIT {
+        let batch = match state
+            .messages
+            .messages_for_recipient(user_id.as_str(), cursor.as_ref(), CATCH_UP_BATCH)
+            .await
+        {
+            Ok(batch) => batch,
+            Err(e) => {
+                eprintln!("Failed to load missed messages for {}: {}", user_id, e);
+                break;
+            }
+        };
+        for message in &batch {
+            tx.send_wait(Message::Text(chat_frame(message))).await;
+            replayed.insert(message.message_id.clone());
+        }
+        count += batch.len() as i64;
+        match batch.last() {
+            Some(last) if batch.len() as i64 == CATCH_UP_BATCH => cursor = Some(last.cursor()),
+            _ => break,
+        }
+    }
+
+    tx.send_wait(ServerMessage::CaughtUp { count }.to_message())
+        .await;
+    replayed
+}
+
+/// Whether `msg` is the live copy of a chat message the replay already sent.
+/// Forgets the id once matched; a message is delivered live at most once.
+fn already_replayed(msg: &Message, replayed: &mut HashSet<String>) -> bool {
+    #[derive(Deserialize)]
+    struct Chat {
+        message_id: String,
+    }
+
+    let Message::Text(text) = msg else {
+        return false;
+    };
+    if replayed.is_empty() || !text.starts_with(r#"{"type":"chat","#) {
+        return false;
+    }
+    serde_json::from_str::<Chat>(text).is_ok_and(|chat| replayed.remove(&chat.message_id))
+}
+
+/// Moves the device's delivery cursor to `message_id` if that message was addressed to its user.
+async fn mark_delivered(state: &AppState, user_id: &UserId, device_id: &str, message_id: &str) {
+    let message = match state.messages.find_message(message_id).await {
+        Ok(Some(message)) => message,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to load message {}: {}", message_id, e);
+            return;
+        }
+    };
+    if message.is_group || *user_id != message.target_id {
+        return;
+    }
+    if let Err(e) = state
+        .messages
+        .advance_delivery_cursor(user_id.as_str(), device_id, &message.cursor())
+        .await
+    {
+        eprintln!("Failed to save delivery cursor for {}: {}", user_id, e);
+    }
+}
+
+/// Persists the user's read cursor and shows the receipt to the conversation.
+async fn mark_read(
+    state: &AppState,
+    user_id: &UserId,
+    target_id: &str,
+    is_group: bool,
+    message_id: &str,
+) {
+    let conversation = Conversation::of(user_id.as_str(), target_id, is_group);
+    if is_group && !state.is_group_member(target_id, user_id) {
+        return;
+    }
+    let message = match state.messages.find_message(message_id).await {
+        Ok(Some(message)) if conversation.contains(&message) => message,
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("Failed to load message {}: {}", message_id, e);
+            return;
+        }
+    };
+    if let Err(e) = state
+        .messages
+        .advance_read_cursor(&conversation, user_id.as_str(), &message.cursor())
+        .await
+    {
+        eprintln!("Failed to save read cursor for {}: {}", user_id, e);
+        return;
+    }
+
+    let frame = ServerMessage::Read {
+        user_id: user_id.to_string(),
+        target_id: target_id.to_string(),
+        is_group,
+        message_id: message.message_id,
+        timestamp: message.timestamp,
+    };
+    state
+        .deliver_to_conversation(user_id, target_id, is_group, frame.to_frame())
+        .await;
+}
+
+/// The message if `user_id` sent it and it has not been deleted yet.
+/// Otherwise tells the socket there is no such message.
+async fn own_live_message(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    message_id: &str,
+) -> Option<ChatMessage> {
+    match state.messages.find_message(message_id).await {
+        Ok(Some(message)) if *user_id == message.sender_id && !message.is_deleted() => {
+            Some(message)
+        }
+        Ok(_) => {
+            let error = ServerMessage::error(
+                ErrorCode::NotFound,
+                format!("no message {} of yours", message_id),
+                None,
+            );
+            tx.send(error.to_message());
+            None
+        }
+        Err(e) => {
+            eprintln!("Failed to load message {}: {}", message_id, e);
+            None
+        }
+    }
+}
+
+/// Edits one of the sender's messages, keeping the old version in its history,
+/// and shows the new version to the original audience.
+async fn edit_message(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    message_id: &str,
+    content: Option<String>,
+    attachments: Option<Vec<String>>,
+) {
+    let Some(mut message) = own_live_message(state, user_id, tx, message_id).await else {
+        return;
+    };
+    // Like sending: someone who left the group no longer writes in it.
+    if message.is_group && !state.can_post_to_group(&message.target_id, user_id).await {
+        let text = format!("not a member of group {}", message.target_id);
+        tx.send(ServerMessage::error(ErrorCode::Forbidden, text, None).to_message());
+        return;
+    }
+    if message.kind == ENCRYPTED_KIND {
+        // The hub cannot check a replacement it cannot read; send a new message.
+        let text = format!("encrypted message {} cannot be edited", message_id);
+        tx.send(ServerMessage::error(ErrorCode::Unsupported, text, None).to_message());
+        return;
+    }
+    message.edit(content, attachments, now_millis());
+    if let Err(e) = state.messages.update_message(&message).await {
+        eprintln!("Failed to save edit of {}: {}", message_id, e);
+        return;
+    }
+
+    let frame = ServerMessage::MessageEdited {
+        message_id: message.message_id.clone(),
+        sender_id: message.sender_id.clone(),
+        target_id: message.target_id.clone(),
+        is_group: message.is_group,
+        content: message.content.clone(),
+        attachments: message.attachments.clone(),
+        edited_at: message.edited_at,
+    };
+    state
+        .deliver_to_conversation(
+            user_id,
+            &message.target_id,
+            message.is_group,
+            frame.to_frame(),
+        )
+        .await;
+}
+
+/// Tombstones one of the sender's messages and tells the original audience.
+async fn delete_message(state: &AppState, user_id: &UserId, tx: &Outbound, message_id: &str) {
+    let Some(mut message) = own_live_message(state, user_id, tx, message_id).await else {
+        return;
+    };
+    message.delete(now_millis());
+    if let Err(e) = state.messages.update_message(&message).await {
+        eprintln!("Failed to save delete of {}: {}", message_id, e);
+        return;
+    }
+
+    let frame = ServerMessage::MessageDeleted {
+        message_id: message.message_id.clone(),
+        sender_id: message.sender_id.clone(),
+        target_id: message.target_id.clone(),
+        is_group: message.is_group,
+        deleted_at: message.deleted_at,
+    };
+    state
+        .deliver_to_conversation(
+            user_id,
+            &message.target_id,
+            message.is_group,
+            frame.to_frame(),
+        )
+        .await;
+}
+
+/// Adds or removes the user's reaction and shows the emoji's reactors to the
+/// message's audience. Only participants of the conversation can react.
+async fn react(
+    state: &AppState,
+    user_id: &UserId,
+    tx: &Outbound,
+    message_id: &str,
+    emoji: &str,
+    add: bool,
+) {
+    let message = match state.messages.find_message(message_id).await {
+        Ok(Some(message)) if !message.is_deleted() => message,
+        Ok(_) => {
+            let text = format!("no message {}", message_id);
+            tx.send(ServerMessage::error(ErrorCode::NotFound, text, None).to_message());
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to load message {}: {}", message_id, e);
+            return;
+        }
+    };
+    // The other side of a DM from the reactor's point of view, or the group.
+    let target_id = if message.is_group || *user_id == message.sender_id {
+        &message.target_id
+    } else {
+        &message.sender_id
+    };
+    let conversation = Conversation::of(user_id.as_str(), target_id, message.is_group);
+    if !conversation.contains(&message)
+        || (message.is_group && !state.is_group_member(target_id, user_id))
+    {
+        let text = format!("not a participant of the conversation of {}", message_id);
+        tx.send(ServerMessage::error(ErrorCode::Forbidden, text, None).to_message());
+        return;
+    }
+
+    let changed = if add {
+        state
+            .messages
+            .add_reaction(message_id, emoji, user_id.as_str())
+            .await
+    } else {
+        state
+            .messages
+            .remove_reaction(message_id, emoji, user_id.as_str())
+            .await
+    };
+    match changed {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            eprintln!("Failed to save reaction on {}: {}", message_id, e);
+            return;
+        }
+    }
+    let reactors = match state.messages.find_message(message_id).await {
+        Ok(Some(updated)) => updated.reactions.get(emoji).cloned().unwrap_or_default(),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            eprintln!("Failed to load message {}: {}", message_id, e);
+            return;
+        }
+    };
+
+    let frame = ServerMessage::Reaction {
+        message_id: message_id.to_string(),
+        target_id: message.target_id.clone(),
+        is_group: message.is_group,
+        user_id: user_id.to_string(),
+        emoji: emoji.to_string(),
+        added: add,
+        reactors,
+    };
+    state
+        .deliver_to_conversation(user_id, target_id, message.is_group, frame.to_frame())
+        .await;
+}
+
+fn rate_limited_frame(
+    kind: FrameKind,
+    retry_after: Duration,
+    client_msg_id: Option<&str>,
+) -> Message {
+    ServerMessage::Error {
+        code: ErrorCode::RateLimited,
+        message: format!("too many {} frames", kind.as_str()),
+        client_msg_id: client_msg_id.map(str::to_string),
+        retry_after_ms: Some(retry_after.as_millis() as u64),
+    }
+    .to_message()
+}
+
+fn ack_frame(client_msg_id: Option<&str>, accepted: &AcceptedMessage) -> Message {
+    ServerMessage::Ack {
+        client_msg_id: client_msg_id.map(str::to_string),
+        message_id: accepted.message_id.clone(),
+        timestamp: accepted.timestamp,
+    }
+    .to_message()
+}
+
+/// Persists a chat, acks it to the sending socket and fans it out.
+/// A resend of an already accepted `client_msg_id` is only re-acked, even
+/// when the first copy went through another node.
+async fn handle_chat(
+    state: &AppState,
+    my_user_id: &UserId,
+    my_tx: &Outbound,
+    message: ChatMessage,
+) {
+    let client_msg_id = message.client_msg_id.as_deref();
+    if let Some(accepted) = client_msg_id.and_then(|id| state.dedup.get(my_user_id, id)) {
+        my_tx.send(ack_frame(client_msg_id, &accepted));
+        return;
+    }
+
+    if message.is_group
+        && !state
+            .can_post_to_group(&message.target_id, my_user_id)
+            .await
+    {
+        let text = format!("not a member of group {}", message.target_id);
+        let error = ServerMessage::error(
+            ErrorCode::Forbidden,
+            text,
+            client_msg_id.map(str::to_string),
+        );
+        my_tx.send(error.to_message());
+        return;
+    }
+    if !references_are_valid(state, &message).await {
+        let error = ServerMessage::error(
+            ErrorCode::Invalid,
+            "reply_to and thread_root must be in this conversation, thread_root a top-level message",
+            client_msg_id.map(str::to_string),
+        );
+        my_tx.send(error.to_message());
+        return;
+    }
+
+    // SAVE TO DB. Without a durable copy there is no ack; the client retries.
+    // The store has the last word on duplicates: the cache above only knows
+    // what went through this node.
+    let stored = match state.messages.insert_message(&message).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            eprintln!("Failed to save message from {}: {}", my_user_id, e);
+            return;
+        }
+    };
+    let original = stored.as_ref().unwrap_or(&message);
+    let accepted = AcceptedMessage {
+        message_id: original.message_id.clone(),
+        timestamp: original.timestamp,
+    };
+    if let Some(id) = client_msg_id {
+        state.dedup.insert(my_user_id, id, accepted.clone());
+    }
+    my_tx.send(ack_frame(client_msg_id, &accepted));
+    if stored.is_some() {
+        return;
+    }
+
+    let msg_str = chat_frame(&message);
+
+    // Group members, or the recipient plus an echo to all of the sender's devices
+    state
+        .deliver_to_conversation(my_user_id, &message.target_id, message.is_group, msg_str)
+        .await;
+
+    if let Some(root_id) = &message.thread_root {
+        if let Err(e) = state.messages.increment_reply_count(root_id).await {
+            eprintln!("Failed to count reply in thread {}: {}", root_id, e);
+        }
+        if message.is_group {
+            notify_thread(state, &message, root_id).await;
+        }
+    }
+}
+
+/// A quoted message must be in the same conversation; a thread root must
+/// also be a top-level message itself.
+async fn references_are_valid(state: &AppState, message: &ChatMessage) -> bool {
+    let conversation = Conversation::of(&message.sender_id, &message.target_id, message.is_group);
+    let references = [
+        (message.reply_to.as_deref(), false),
+        (message.thread_root.as_deref(), true),
+    ];
+    for (message_id, is_root) in references {
+        let Some(message_id) = message_id else {
+            continue;
+        };
+        match state.messages.find_message(message_id).await {
+            Ok(Some(referenced))
+                if conversation.contains(&referenced)
+                    && !(is_root && referenced.thread_root.is_some()) => {}
+            Ok(_) => return false,
+            Err(e) => {
+                eprintln!("Failed to load message {}: {}", message_id, e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Sends a group thread reply to the thread's participants who are not in the
+/// group's room right now and so did not get it as a chat. Each node checks
+/// the room for its own sockets. Participants the group no longer lets in
+/// are left out.
+async fn notify_thread(state: &AppState, reply: &ChatMessage, root_id: &str) {
+    let Some(group) = state.find_group(&reply.target_id).await else {
+        return;
+    };
+    let mut participants = match state.messages.thread_participants(root_id).await {
+        Ok(participants) => participants,
+        Err(e) => {
+            eprintln!("Failed to load participants of thread {}: {}", root_id, e);
+            return;
+        }
+    };
+    if let Ok(Some(root)) = state.messages.find_message(root_id).await {
+        participants.push(root.sender_id);
+    }
+    participants.sort();
+    participants.dedup();
+    participants
+        .retain(|participant| *participant != reply.sender_id && group.can_join(participant));
+    if participants.is_empty() {
+        return;
+    }
+
+    let route = Route::OutsideGroup {
+        group_id: reply.target_id.clone(),
+        user_ids: participants,
+    };
+    let frame = ServerMessage::ThreadReply(reply.clone()).to_frame();
+    state.deliver(route, frame).await;
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{assert_silent, connect, get_json, recv_json, send_json, spawn_hub};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+
+#[tokio::test]
+async fn chat_is_acked_with_server_message_id() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "hi", "kind": "text", "client_msg_id": "c-1" }),
+    )
+    .await;
+
+    let ack = recv_json(&mut alice).await;
+    assert_eq!(ack["type"], "ack");
+    assert_eq!(ack["client_msg_id"], "c-1");
+    let message_id = ack["message_id"].as_str().unwrap().to_string();
+    assert!(!message_id.is_empty());
+
+    // Both the recipient and the sender's echo carry the same ids.
+    let received = recv_json(&mut bob).await;
+    assert_eq!(received["message_id"], message_id.as_str());
+    assert_eq!(received["client_msg_id"], "c-1");
+    let echo = recv_json(&mut alice).await;
+    assert_eq!(echo["message_id"], message_id.as_str());
+
+    let (_, page) = get_json(&state, "alice", "/messages?target_id=bob").await;
+    assert_eq!(page["messages"][0]["message_id"], message_id.as_str());
+    assert_eq!(page["messages"][0]["client_msg_id"], "c-1");
+}
+
+#[tokio::test]
+async fn resend_is_reacked_but_not_stored_or_broadcast_twice() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    let chat = json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "once", "kind": "text", "client_msg_id": "c-2" });
+
+    send_json(&mut alice, chat.clone()).await;
+    let first_ack = recv_json(&mut alice).await;
+    recv_json(&mut alice).await; // echo
+    recv_json(&mut bob).await;
+
+    send_json(&mut alice, chat).await;
+    let second_ack = recv_json(&mut alice).await;
+    assert_eq!(second_ack["type"], "ack");
+    assert_eq!(second_ack["message_id"], first_ack["message_id"]);
+    assert_eq!(second_ack["timestamp"], first_ack["timestamp"]);
+
+    assert_silent(&mut bob).await;
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn same_client_id_from_different_senders_is_not_a_duplicate() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "a", "kind": "text", "client_msg_id": "same" }),
+    )
+    .await;
+    let alice_ack = recv_json(&mut alice).await;
+    recv_json(&mut bob).await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "chat", "target_id": "alice", "is_group": false, "content": "b", "kind": "text", "client_msg_id": "same" }),
+    )
+    .await;
+    let bob_ack = recv_json(&mut bob).await;
+    assert_eq!(bob_ack["type"], "ack");
+    assert_ne!(bob_ack["message_id"], alice_ack["message_id"]);
+}
@@ -2,20 +2,18 @@ use axum::{
     routing::get,
     Router,
 };
-use std::sync::Arc;
 use tokio::net::TcpListener;
-use dashmap::DashMap;
-use realtime_hub::{db, ws};
+use realtime_hub::ws;
 use realtime_hub::ws::AppState;
 use serde::{Deserialize, Serialize};
 use jsonwebtoken::{encode, EncodingKey, Header};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use futures::StreamExt;
+use tokio_tungstenite::connect_async;
 use url::Url;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
-    userId: String,
+    #[serde(rename = "userId")]
+    user_id: String,
     email: String,
     exp: usize,
 }
@@ -24,19 +22,9 @@ struct Claims {
 async fn test_websocket_auth() {
     // 1. Setup Environment
     std::env::set_var("JWT_SECRET", "Secret");
-    // Ensure we don't break logic if MONGODB_URL is missing, although connect_db expects it.
-    // Assuming local mongo is running.
-    if std::env::var("MONGODB_URL").is_err() {
-        std::env::set_var("MONGODB_URL", "mongodb://localhost:27017/realtime_hub_test");
-    }
 
-    // 2. Setup Server
-    let db_handle = db::connect_db().await.expect("Failed to connect to DB");
-    let state = AppState {
-        connections: Arc::new(DashMap::new()),
-        groups: Arc::new(DashMap::new()),
-        db: db_handle,
-    };
+    // 2. Setup Server (in-memory store, no MongoDB needed)
+    let state = AppState::in_memory();
 
     let app = Router::new()
         .route("/ws", get(ws::ws_handler))
@@ -52,7 +40,7 @@ async fn test_websocket_auth() {
 
     // 3. Generate Valid Token
     let claims = Claims {
-        userId: "test-user-1".to_string(),
+        user_id: "test-user-1".to_string(),
         email: "test@example.com".to_string(),
         exp: 20000000000, // far future
     };
@@ -0,0 +1,90 @@
+mod common;
+
+use axum::extract::ws::Message as HubMessage;
+use common::{connect, get_json, spawn_hub, wait_until};
+use futures::StreamExt;
+use realtime_hub::bus::Route;
+use realtime_hub::registry::Outbound;
+use realtime_hub::ws::AppState;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+fn text(body: &str) -> HubMessage {
+    HubMessage::Text(body.to_string())
+}
+
+#[tokio::test]
+async fn ephemeral_frames_are_shed_before_the_queue_fills() {
+    let (outbound, mut rx) = Outbound::channel(4);
+
+    assert!(outbound.send(text("chat 1")));
+    assert!(outbound.send_ephemeral(text("typing 1")));
+    // Half full: typing is dropped, chats still fit.
+    assert!(!outbound.send_ephemeral(text("typing 2")));
+    assert!(outbound.send(text("chat 2")));
+    assert!(outbound.send(text("chat 3")));
+    assert_eq!(outbound.depth(), 4);
+    assert_eq!(outbound.dropped(), 1);
+
+    // A chat that does not fit flags the session for disconnection.
+    assert!(!outbound.send(text("chat 4")));
+    tokio::time::timeout(Duration::from_secs(1), outbound.overflowed())
+        .await
+        .expect("overflow not signalled");
+
+    rx.recv().await.unwrap();
+    assert_eq!(outbound.depth(), 3);
+}
+
+#[tokio::test]
+async fn slow_consumers_are_disconnected_with_a_close_code() {
+    let state = AppState::in_memory().with_queue_capacity(8);
+    let addr = spawn_hub(state.clone()).await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    // Bob stops reading; once the socket buffers are full his queue fills up.
+    let frame = format!(
+        "{{\"type\":\"chat\",\"content\":\"{}\"}}",
+        "x".repeat(64 * 1024)
+    );
+    for _ in 0..2000 {
+        if !state.connections.is_online("bob") {
+            break;
+        }
+        let route = Route::User {
+            user_id: "bob".to_string(),
+        };
+        state.deliver(route, frame.clone()).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    wait_until(|| !state.connections.is_online("bob")).await;
+
+    // Everything queued before the overflow still arrives. The close frame
+    // only makes it if the socket had room for it; either way the stream ends.
+    loop {
+        match tokio::time::timeout(Duration::from_secs(5), bob.next()).await {
+            Ok(Some(Ok(Message::Close(close)))) => {
+                assert_eq!(close.unwrap().code, CloseCode::Again);
+                break;
+            }
+            Ok(Some(Ok(_))) => continue,
+            Ok(Some(Err(_))) | Ok(None) => break,
+            Err(_) => panic!("socket still open"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn queue_depth_is_reported() {
+    let state = AppState::in_memory().with_queue_capacity(32);
+    let addr = spawn_hub(state.clone()).await;
+    let _alice = connect(addr, &state, "alice").await;
+    let _bob = connect(addr, &state, "bob").await;
+
+    let (_, report) = get_json(&state, "alice", "/diagnostics/queues").await;
+    assert_eq!(report["capacity"], 32);
+    assert_eq!(report["sessions"], 2);
+    assert_eq!(report["mine"].as_array().unwrap().len(), 1);
+    assert_eq!(report["mine"][0]["capacity"], 32);
+}
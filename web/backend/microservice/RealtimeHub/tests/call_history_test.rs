@@ -0,0 +1,148 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{get_anonymous, get_json, post_json};
+use realtime_hub::store::{CallRecord, CallStatus, CallStore, MemoryStore};
+use realtime_hub::ws::AppState;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HOUR: i64 = 3_600_000;
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+fn call(call_id: &str, caller: &str, callee: &str, status: CallStatus, at: i64) -> CallRecord {
+    let answered = !matches!(
+        status,
+        CallStatus::Missed | CallStatus::Declined | CallStatus::Busy
+    );
+    CallRecord {
+        call_id: call_id.to_string(),
+        caller_id: caller.to_string(),
+        callee_id: callee.to_string(),
+        video: false,
+        status,
+        started_at: at,
+        answered_at: answered.then_some(at + 1_000),
+        connected_at: answered.then_some(at + 2_000),
+        ended_at: Some(at + 60_000),
+        ended_by: None,
+    }
+}
+
+/// Alice's history: an hour apart, the oldest first.
+async fn history() -> AppState {
+    let store = Arc::new(MemoryStore::new());
+    let base = now_millis() - 10 * HOUR;
+    let calls = [
+        call("c1", "alice", "bob", CallStatus::Ended, base),
+        call("c2", "bob", "alice", CallStatus::Missed, base + HOUR),
+        call(
+            "c3",
+            "carol",
+            "alice",
+            CallStatus::Declined,
+            base + 2 * HOUR,
+        ),
+        call("c4", "carol", "alice", CallStatus::Missed, base + 3 * HOUR),
+        call("c5", "bob", "carol", CallStatus::Missed, base + 4 * HOUR),
+    ];
+    for call in &calls {
+        store.insert_call(call).await.unwrap();
+    }
+    AppState::with_store(store)
+}
+
+fn ids(page: &serde_json::Value) -> Vec<&str> {
+    page["calls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["call_id"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn history_is_typed_from_the_callers_side() {
+    let state = history().await;
+
+    let (status, _) = get_anonymous(&state, "/calls").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, page) = get_json(&state, "alice", "/calls").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ids(&page), ["c4", "c3", "c2", "c1"]);
+    assert_eq!(page["missed_count"], 2);
+    assert!(page["next_cursor"].is_null());
+
+    let outgoing = &page["calls"][3];
+    assert_eq!(outgoing["counterpart_id"], "bob");
+    assert_eq!(outgoing["direction"], "outgoing");
+    assert_eq!(outgoing["status"], "ended");
+    assert_eq!(outgoing["duration_ms"], 58_000);
+    assert!(outgoing.get("caller_id").is_none());
+
+    let incoming = &page["calls"][0];
+    assert_eq!(incoming["counterpart_id"], "carol");
+    assert_eq!(incoming["direction"], "incoming");
+    assert_eq!(incoming["status"], "missed");
+    assert!(incoming["duration_ms"].is_null());
+
+    let (status, detail) = get_json(&state, "alice", "/calls/c3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(detail["counterpart_id"], "carol");
+    assert_eq!(detail["status"], "declined");
+    let (status, _) = get_json(&state, "alice", "/calls/c5").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn history_filters_and_pages() {
+    let state = history().await;
+
+    let (_, page) = get_json(&state, "alice", "/calls?status=missed,declined").await;
+    assert_eq!(ids(&page), ["c4", "c3", "c2"]);
+    let (status, _) = get_json(&state, "alice", "/calls?status=lost").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, all) = get_json(&state, "alice", "/calls").await;
+    let since = all["calls"][2]["started_at"].as_i64().unwrap();
+    let until = all["calls"][0]["started_at"].as_i64().unwrap();
+    let uri = format!("/calls?since={}&until={}", since, until);
+    let (_, page) = get_json(&state, "alice", &uri).await;
+    assert_eq!(ids(&page), ["c3", "c2"]);
+
+    let (_, page) = get_json(&state, "alice", "/calls?limit=3").await;
+    assert_eq!(ids(&page), ["c4", "c3", "c2"]);
+    let uri = format!(
+        "/calls?limit=3&before={}",
+        page["next_cursor"].as_str().unwrap()
+    );
+    let (_, page) = get_json(&state, "alice", &uri).await;
+    assert_eq!(ids(&page), ["c1"]);
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn seeing_missed_calls_clears_the_badge() {
+    let state = history().await;
+
+    let (status, _) = post_json(&state, "alice", "/calls/seen").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, page) = get_json(&state, "alice", "/calls").await;
+    assert_eq!(page["missed_count"], 0);
+
+    let missed = call("c6", "bob", "alice", CallStatus::Missed, now_millis());
+    state.calls.insert_call(&missed).await.unwrap();
+    let (_, page) = get_json(&state, "alice", "/calls").await;
+    assert_eq!(page["missed_count"], 1);
+
+    // Bob's badge is his own.
+    let (_, page) = get_json(&state, "bob", "/calls").await;
+    assert_eq!(page["missed_count"], 0);
+}
@@ -0,0 +1,207 @@
+mod common;
+
+use common::{
+    assert_silent, connect, get_json, recv_json, send_json, spawn_hub, wait_until, Client,
+};
+use realtime_hub::calls::CallConfig;
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+async fn invite(caller: &mut Client, callee_id: &str) {
+    send_json(
+        caller,
+        json!({ "type": "call_invite", "target_id": callee_id, "video": true }),
+    )
+    .await;
+}
+
+async fn call_frame(client: &mut Client, call_id: &Value, kind: &str) {
+    send_json(client, json!({ "type": kind, "call_id": call_id })).await;
+}
+
+/// Next `call_state` on each socket, which must all agree on the status.
+async fn expect_state(clients: &mut [&mut Client], status: &str) -> Value {
+    let mut last = Value::Null;
+    for client in clients.iter_mut() {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["type"], "call_state", "{}", frame);
+        assert_eq!(frame["status"], status, "{}", frame);
+        last = frame;
+    }
+    last
+}
+
+#[tokio::test]
+async fn answered_call_is_one_complete_record() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    invite(&mut alice, "bob").await;
+    let ringing = expect_state(&mut [&mut alice, &mut bob], "ringing").await;
+    let call_id = ringing["call_id"].clone();
+    assert_eq!(ringing["caller_id"], "alice");
+    assert_eq!(ringing["video"], true);
+
+    // Only the callee can answer.
+    call_frame(&mut alice, &call_id, "call_accept").await;
+    assert_eq!(recv_json(&mut alice).await["code"], "invalid");
+
+    call_frame(&mut bob, &call_id, "call_accept").await;
+    expect_state(&mut [&mut alice, &mut bob], "accepted").await;
+    call_frame(&mut alice, &call_id, "call_connected").await;
+    expect_state(&mut [&mut alice, &mut bob], "connected").await;
+    call_frame(&mut bob, &call_id, "call_connected").await;
+    assert_silent(&mut alice).await;
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    call_frame(&mut bob, &call_id, "call_end").await;
+    let ended = expect_state(&mut [&mut alice, &mut bob], "ended").await;
+    assert_eq!(ended["ended_by"], "bob");
+    let connected_at = ended["connected_at"].as_i64().unwrap();
+    assert!(ended["ended_at"].as_i64().unwrap() >= connected_at + 20);
+
+    // Hanging up twice changes nothing.
+    call_frame(&mut alice, &call_id, "call_end").await;
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "alice", "/calls").await;
+    let calls = page["calls"].as_array().unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0]["call_id"], call_id);
+    assert_eq!(calls[0]["counterpart_id"], "bob");
+    assert_eq!(calls[0]["direction"], "outgoing");
+    assert_eq!(calls[0]["ended_at"], ended["ended_at"]);
+    assert!(calls[0]["answered_at"].is_i64());
+    assert!(calls[0]["duration_ms"].as_i64().unwrap() >= 20);
+}
+
+#[tokio::test]
+async fn declined_and_cancelled_calls() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    invite(&mut alice, "bob").await;
+    let call_id = expect_state(&mut [&mut alice, &mut bob], "ringing").await["call_id"].clone();
+    call_frame(&mut bob, &call_id, "call_decline").await;
+    expect_state(&mut [&mut alice, &mut bob], "declined").await;
+
+    invite(&mut alice, "bob").await;
+    let call_id = expect_state(&mut [&mut alice, &mut bob], "ringing").await["call_id"].clone();
+    call_frame(&mut alice, &call_id, "call_end").await;
+    let missed = expect_state(&mut [&mut alice, &mut bob], "missed").await;
+    assert_eq!(missed["ended_by"], "alice");
+
+    let mut mallory = connect(addr, &state, "mallory").await;
+    call_frame(&mut mallory, &call_id, "call_end").await;
+    assert_eq!(recv_json(&mut mallory).await["code"], "not_found");
+}
+
+#[tokio::test]
+async fn unanswered_call_times_out_as_missed() {
+    let state = AppState::in_memory().with_call_config(CallConfig {
+        ring_timeout: Duration::from_millis(200),
+        ..Default::default()
+    });
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    invite(&mut alice, "bob").await;
+    let call_id = expect_state(&mut [&mut alice, &mut bob], "ringing").await["call_id"].clone();
+    let missed = expect_state(&mut [&mut alice, &mut bob], "missed").await;
+    assert!(missed["ended_by"].is_null());
+
+    call_frame(&mut bob, &call_id, "call_accept").await;
+    assert_eq!(recv_json(&mut bob).await["code"], "invalid");
+}
+
+#[tokio::test]
+async fn callee_in_another_call_is_busy() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let mut carol = connect(addr, &state, "carol").await;
+
+    invite(&mut alice, "bob").await;
+    let call_id = expect_state(&mut [&mut alice, &mut bob], "ringing").await["call_id"].clone();
+    call_frame(&mut bob, &call_id, "call_accept").await;
+    expect_state(&mut [&mut alice, &mut bob], "accepted").await;
+
+    invite(&mut carol, "bob").await;
+    let busy = expect_state(&mut [&mut carol, &mut bob], "busy").await;
+    assert!(busy["ended_at"].is_i64());
+
+    // Nor can alice start a second call while in one.
+    invite(&mut alice, "carol").await;
+    assert_eq!(recv_json(&mut alice).await["code"], "invalid");
+}
+
+#[tokio::test]
+async fn calling_each_other_at_once_is_glare() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    invite(&mut alice, "bob").await;
+    let first = expect_state(&mut [&mut alice, &mut bob], "ringing").await;
+    invite(&mut bob, "alice").await;
+    let refusal = recv_json(&mut bob).await;
+    assert_eq!(refusal["code"], "glare");
+    let standing = recv_json(&mut bob).await;
+    assert_eq!(standing["type"], "call_state");
+    assert_eq!(standing["call_id"], first["call_id"]);
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "bob", "/calls").await;
+    assert_eq!(page["calls"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn disconnecting_ends_live_calls() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    invite(&mut alice, "bob").await;
+    let call_id = expect_state(&mut [&mut alice, &mut bob], "ringing").await["call_id"].clone();
+    call_frame(&mut bob, &call_id, "call_accept").await;
+    expect_state(&mut [&mut alice, &mut bob], "accepted").await;
+
+    drop(bob);
+    wait_until(|| !state.connections.is_online("bob")).await;
+    loop {
+        let frame = recv_json(&mut alice).await;
+        if frame["type"] == "call_state" {
+            assert_eq!(frame["status"], "ended");
+            assert_eq!(frame["ended_by"], "bob");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn signals_need_a_live_call() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let signal = json!({ "type": "signal", "target_id": "bob", "payload": { "type": "offer" } });
+
+    send_json(&mut alice, signal.clone()).await;
+    assert_eq!(recv_json(&mut alice).await["code"], "not_found");
+    assert_silent(&mut bob).await;
+
+    // Ringing is enough, the offer goes out before the callee picks up.
+    invite(&mut alice, "bob").await;
+    expect_state(&mut [&mut alice, &mut bob], "ringing").await;
+    send_json(&mut alice, signal).await;
+    assert_eq!(recv_json(&mut bob).await["type"], "signal");
+}
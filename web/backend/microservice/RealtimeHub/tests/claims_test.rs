@@ -0,0 +1,90 @@
+use jsonwebtoken::{encode, EncodingKey, Header};
+use realtime_hub::api;
+use realtime_hub::auth::{decode_token, UserId};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio_tungstenite::connect_async;
+use url::Url;
+
+const SECRET: &str = "Secret";
+
+fn sign(claims: serde_json::Value) -> String {
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+#[test]
+fn accepts_numeric_user_id_from_auth_service() {
+    let token = sign(json!({ "userId": 42, "email": "a@example.com", "exp": 20000000000u64 }));
+    let claims = decode_token(&token, SECRET).expect("numeric userId should decode");
+    assert_eq!(claims.user_id, UserId::new("42"));
+    assert_eq!(claims.email, "a@example.com");
+}
+
+#[test]
+fn accepts_string_user_id() {
+    let token =
+        sign(json!({ "userId": "user-7", "email": "b@example.com", "exp": 20000000000u64 }));
+    let claims = decode_token(&token, SECRET).expect("string userId should decode");
+    assert_eq!(claims.user_id.as_str(), "user-7");
+}
+
+#[test]
+fn accepts_legacy_user_id_field() {
+    let token =
+        sign(json!({ "user_id": "legacy-1", "email": "c@example.com", "exp": 20000000000u64 }));
+    let claims = decode_token(&token, SECRET).expect("legacy user_id should decode");
+    assert_eq!(claims.user_id.as_str(), "legacy-1");
+}
+
+#[test]
+fn rejects_missing_or_empty_user_id() {
+    let missing = sign(json!({ "email": "d@example.com", "exp": 20000000000u64 }));
+    assert!(decode_token(&missing, SECRET).is_err());
+
+    let empty = sign(json!({ "userId": "", "email": "d@example.com", "exp": 20000000000u64 }));
+    assert!(decode_token(&empty, SECRET).is_err());
+}
+
+#[test]
+fn rejects_wrong_secret() {
+    let token = sign(json!({ "userId": 1, "email": "e@example.com", "exp": 20000000000u64 }));
+    assert!(decode_token(&token, "other-secret").is_err());
+}
+
+#[tokio::test]
+async fn websocket_accepts_auth_service_token() {
+    std::env::set_var("JWT_SECRET", SECRET);
+
+    let state = AppState::in_memory();
+    let connections = state.connections.clone();
+
+    let app = api::router(state);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let token = sign(json!({ "userId": 1001, "email": "ws@example.com", "exp": 20000000000u64 }));
+    let url = Url::parse(&format!("ws://{}/ws?token={}", addr, token)).unwrap();
+    let (mut ws_stream, _) = connect_async(url)
+        .await
+        .expect("Auth service token should be accepted");
+
+    // The socket is registered under the normalized string id.
+    for _ in 0..50 {
+        if connections.is_online("1001") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(connections.is_online("1001"));
+
+    ws_stream.close(None).await.unwrap();
+}
@@ -0,0 +1,325 @@
+mod common;
+
+use common::{connect, connected_call, get_json, recv_json, send_json, spawn_hub, wait_until};
+use realtime_hub::bus::LocalBus;
+use realtime_hub::sessions::LocalSessions;
+use realtime_hub::store::{CallRecord, CallStatus, Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Two hub nodes sharing one store, one bus and one session store, like two
+/// instances behind the gateway.
+fn two_nodes() -> (AppState, AppState) {
+    let store = Arc::new(MemoryStore::new());
+    let bus = Arc::new(LocalBus::new());
+    let sessions = Arc::new(LocalSessions::new());
+    store.put_group(Group {
+        group_id: "rust".to_string(),
+        members: vec!["alice".to_string(), "bob".to_string()],
+        privacy: Privacy::Private,
+    });
+    let node_a = AppState::with_store(store.clone())
+        .with_bus(bus.clone())
+        .with_sessions(sessions.clone());
+    let node_b = AppState::with_store(store)
+        .with_bus(bus)
+        .with_sessions(sessions);
+    (node_a, node_b)
+}
+
+#[tokio::test]
+async fn direct_message_crosses_nodes() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let mut bob = connect(addr_b, &node_b, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "across", "kind": "text" }),
+    )
+    .await;
+
+    let received = recv_json(&mut bob).await;
+    assert_eq!(received["content"], "across");
+    assert_eq!(received["sender_id"], "alice");
+}
+
+#[tokio::test]
+async fn sender_devices_on_other_nodes_get_the_echo_once() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice_a = connect(addr_a, &node_a, "alice").await;
+    let mut alice_b = connect(addr_b, &node_b, "alice").await;
+
+    send_json(
+        &mut alice_a,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "mine", "kind": "text" }),
+    )
+    .await;
+
+    assert_eq!(recv_json(&mut alice_a).await["type"], "ack");
+    assert_eq!(recv_json(&mut alice_a).await["content"], "mine");
+    assert_eq!(recv_json(&mut alice_b).await["content"], "mine");
+    common::assert_silent(&mut alice_a).await;
+    common::assert_silent(&mut alice_b).await;
+}
+
+#[tokio::test]
+async fn group_broadcast_reaches_members_on_every_node() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let mut bob = connect(addr_b, &node_b, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    send_json(
+        &mut bob,
+        json!({ "type": "join_group", "user_id": "bob", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| node_a.groups.contains_key("rust") && node_b.groups.contains_key("rust")).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "hi all", "kind": "text" }),
+    )
+    .await;
+
+    assert_eq!(recv_json(&mut bob).await["content"], "hi all");
+}
+
+#[tokio::test]
+async fn signal_crosses_nodes() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let mut bob = connect(addr_b, &node_b, "bob").await;
+    connected_call(&node_a, "alice", "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "signal", "target_id": "bob", "payload": { "type": "offer", "sdp": "v=0" } }),
+    )
+    .await;
+
+    let relayed = recv_json(&mut bob).await;
+    assert_eq!(relayed["type"], "signal");
+    assert_eq!(relayed["payload"]["type"], "offer");
+}
+
+#[tokio::test]
+async fn presence_is_shared_between_nodes() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let subscribe = json!({ "type": "subscribe_presence", "user_ids": ["bob"] });
+    send_json(&mut alice, subscribe).await;
+    assert_eq!(recv_json(&mut alice).await["status"], "offline");
+
+    // Online as soon as bob connects anywhere, and only once.
+    let bob_b = connect(addr_b, &node_b, "bob").await;
+    assert_eq!(recv_json(&mut alice).await["status"], "online");
+    let mut bob_a = connect(addr_a, &node_a, "bob").await;
+    common::assert_silent(&mut alice).await;
+
+    // Still online while bob keeps a session on the other node.
+    drop(bob_b);
+    wait_until(|| !node_b.connections.is_online("bob")).await;
+    common::assert_silent(&mut alice).await;
+    let (_, presence) = get_json(&node_b, "carol", "/presence?user_ids=bob").await;
+    assert_eq!(presence[0]["status"], "online");
+
+    bob_a.close(None).await.unwrap();
+    assert_eq!(recv_json(&mut alice).await["status"], "offline");
+}
+
+#[tokio::test]
+async fn calls_survive_while_the_user_is_connected_elsewhere() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let mut bob_a = connect(addr_a, &node_a, "bob").await;
+    let mut bob_b = connect(addr_b, &node_b, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "call_invite", "target_id": "bob", "video": false }),
+    )
+    .await;
+    let call_id = recv_json(&mut alice).await["call_id"].clone();
+    recv_json(&mut bob_a).await;
+    recv_json(&mut bob_b).await;
+    send_json(
+        &mut bob_b,
+        json!({ "type": "call_accept", "call_id": call_id }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["status"], "accepted");
+    recv_json(&mut bob_a).await;
+
+    // Bob's only session on node B closes, but he is still on node A.
+    drop(bob_b);
+    wait_until(|| !node_b.connections.is_online("bob")).await;
+    common::assert_silent(&mut alice).await;
+
+    drop(bob_a);
+    let ended = recv_json(&mut alice).await;
+    assert_eq!(ended["status"], "ended");
+    assert_eq!(ended["ended_by"], "bob");
+}
+
+#[tokio::test]
+async fn conferences_keep_a_user_connected_elsewhere() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut bob_a = connect(addr_a, &node_a, "bob").await;
+    let mut bob_b = connect(addr_b, &node_b, "bob").await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    for (client, node, user) in [
+        (&mut bob_a, &node_a, "bob"),
+        (&mut bob_b, &node_b, "bob"),
+        (&mut alice, &node_a, "alice"),
+    ] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+        wait_until(|| node.is_group_member("rust", &user.into())).await;
+    }
+
+    send_json(
+        &mut bob_b,
+        json!({ "type": "conference_join", "group_id": "rust" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["participants"], json!(["bob"]));
+
+    // Bob's only session on node B closes, but he is still on node A.
+    drop(bob_b);
+    wait_until(|| !node_b.connections.is_online("bob")).await;
+    common::assert_silent(&mut alice).await;
+
+    drop(bob_a);
+    loop {
+        let frame = recv_json(&mut alice).await;
+        if frame["type"] == "conference_roster" {
+            assert_eq!(frame["participants"], json!([]));
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn thread_replies_are_not_repeated_to_room_members_on_other_nodes() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    let mut bob = connect(addr_b, &node_b, "bob").await;
+    for (client, node, user) in [(&mut alice, &node_a, "alice"), (&mut bob, &node_b, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+        wait_until(|| node.is_group_member("rust", &user.into())).await;
+    }
+
+    let chat = |thread_root: Option<&Value>| json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "hi", "kind": "text", "thread_root": thread_root });
+    send_json(&mut alice, chat(None)).await;
+    let root_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await;
+    recv_json(&mut bob).await;
+
+    // Alice is in the room on her node, so the reply reaches her as a chat only.
+    send_json(&mut bob, chat(Some(&root_id))).await;
+    recv_json(&mut bob).await; // ack
+    recv_json(&mut bob).await; // echo
+    let reply = recv_json(&mut alice).await;
+    assert_eq!(reply["type"], "chat");
+    assert_eq!(reply["thread_root"], root_id);
+    common::assert_silent(&mut alice).await;
+}
+
+#[tokio::test]
+async fn a_resend_through_another_node_is_not_stored_twice() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+    let mut bob = connect(addr_b, &node_b, "bob").await;
+
+    let chat = json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "once", "kind": "text", "client_msg_id": "c-1" });
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+    send_json(&mut alice, chat.clone()).await;
+    let first_ack = recv_json(&mut alice).await;
+    assert_eq!(recv_json(&mut bob).await["content"], "once");
+
+    // The ack got lost and alice reconnected through the other node.
+    let mut alice = connect(addr_b, &node_b, "alice").await;
+    send_json(&mut alice, chat).await;
+    let second_ack = recv_json(&mut alice).await;
+    assert_eq!(second_ack["type"], "ack");
+    assert_eq!(second_ack["message_id"], first_ack["message_id"]);
+    assert_eq!(second_ack["timestamp"], first_ack["timestamp"]);
+
+    common::assert_silent(&mut bob).await;
+    let (_, page) = get_json(&node_a, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn an_invite_waits_for_one_crossing_it_on_another_node() {
+    let (node_a, node_b) = two_nodes();
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let mut alice = connect(addr_a, &node_a, "alice").await;
+
+    // Node B is in the middle of bob's invite to alice.
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let now = now.as_millis() as i64;
+    assert!(node_b
+        .calls
+        .claim_line("alice", "b", now + 5000)
+        .await
+        .unwrap());
+    send_json(
+        &mut alice,
+        json!({ "type": "call_invite", "target_id": "bob" }),
+    )
+    .await;
+    common::assert_silent(&mut alice).await;
+
+    let call = CallRecord {
+        call_id: "crossing".to_string(),
+        caller_id: "bob".to_string(),
+        callee_id: "alice".to_string(),
+        video: false,
+        status: CallStatus::Ringing,
+        started_at: now,
+        answered_at: None,
+        connected_at: None,
+        ended_at: None,
+        ended_by: None,
+    };
+    node_b.calls.insert_call(&call).await.unwrap();
+    node_b.calls.release_line("alice", "b").await.unwrap();
+
+    assert_eq!(recv_json(&mut alice).await["code"], "glare");
+    assert_eq!(recv_json(&mut alice).await["call_id"], "crossing");
+    let (_, page) = get_json(&node_a, "alice", "/calls").await;
+    assert_eq!(page["calls"].as_array().unwrap().len(), 1);
+}
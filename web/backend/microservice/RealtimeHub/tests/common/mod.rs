@@ -0,0 +1,213 @@
+// Shared helpers for the integration tests. Not every test file uses all of them.
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use futures::{SinkExt, StreamExt};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use realtime_hub::api;
+use realtime_hub::store::{CallRecord, CallStatus, Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tower::ServiceExt;
+use url::Url;
+
+pub const SECRET: &str = "Secret";
+
+pub type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Access token shaped like the ones the Auth service signs.
+pub fn token(user_id: &str) -> String {
+    encode(
+        &Header::default(),
+        &json!({ "userId": user_id, "email": format!("{}@example.com", user_id), "exp": 20000000000u64 }),
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+pub async fn spawn_hub(state: AppState) -> SocketAddr {
+    std::env::set_var("JWT_SECRET", SECRET);
+
+    state.spawn_bus_listener();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = api::router(state);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    addr
+}
+
+/// Connects `user_id`, waits until the hub has registered the socket and
+/// consumes the `caught_up` marker that ends the offline replay.
+pub async fn connect(addr: SocketAddr, state: &AppState, user_id: &str) -> Client {
+    let mut client = connect_raw(addr, state, user_id).await;
+    loop {
+        if recv_json(&mut client).await["type"] == "caught_up" {
+            break;
+        }
+    }
+    client
+}
+
+/// Like `connect`, but leaves the offline replay on the socket.
+pub async fn connect_raw(addr: SocketAddr, state: &AppState, user_id: &str) -> Client {
+    let url = format!("ws://{}/ws?token={}", addr, token(user_id));
+    open_socket(state, user_id, &url).await
+}
+
+/// Like `connect_raw`, as the named device of the user.
+pub async fn connect_device(
+    addr: SocketAddr,
+    state: &AppState,
+    user_id: &str,
+    device_id: &str,
+) -> Client {
+    let url = format!(
+        "ws://{}/ws?token={}&device_id={}",
+        addr,
+        token(user_id),
+        device_id
+    );
+    open_socket(state, user_id, &url).await
+}
+
+async fn open_socket(state: &AppState, user_id: &str, url: &str) -> Client {
+    let sessions_before = state.connections.session_count(user_id);
+    let url = Url::parse(url).unwrap();
+    let (client, _) = connect_async(url).await.expect("WS connect failed");
+    wait_until(|| state.connections.session_count(user_id) > sessions_before).await;
+    client
+}
+
+pub async fn wait_until(mut condition: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition not met in time");
+}
+
+pub async fn send_json(client: &mut Client, value: Value) {
+    client.send(Message::Text(value.to_string())).await.unwrap();
+}
+
+/// Next JSON text frame, failing the test after a second of silence.
+pub async fn recv_json(client: &mut Client) -> Value {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("timed out waiting for a frame")
+            .expect("socket closed")
+            .expect("socket error");
+        if let Message::Text(text) = msg {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// Asserts that nothing arrives on the socket for a short while.
+pub async fn assert_silent(client: &mut Client) {
+    let next = tokio::time::timeout(Duration::from_millis(150), client.next()).await;
+    assert!(next.is_err(), "unexpected frame: {:?}", next);
+}
+
+/// GET against the REST routes as `user_id`, without going through a socket.
+pub async fn get_json(state: &AppState, user_id: &str, uri: &str) -> (StatusCode, Value) {
+    let request = Request::get(uri)
+        .header("Authorization", format!("Bearer {}", token(user_id)))
+        .body(Body::empty())
+        .unwrap();
+    send_request(state, request).await
+}
+
+/// POST with an empty body as `user_id`.
+pub async fn post_json(state: &AppState, user_id: &str, uri: &str) -> (StatusCode, Value) {
+    let request = Request::post(uri)
+        .header("Authorization", format!("Bearer {}", token(user_id)))
+        .body(Body::empty())
+        .unwrap();
+    send_request(state, request).await
+}
+
+/// Any method as `user_id`, with a JSON body if given.
+pub async fn request_as(
+    state: &AppState,
+    user_id: &str,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {}", token(user_id)));
+    let request = match body {
+        Some(body) => builder
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    };
+    send_request(state, request.unwrap()).await
+}
+
+/// GET without any credentials.
+pub async fn get_anonymous(state: &AppState, uri: &str) -> (StatusCode, Value) {
+    send_request(state, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+async fn send_request(state: &AppState, request: Request<Body>) -> (StatusCode, Value) {
+    std::env::set_var("JWT_SECRET", SECRET);
+
+    let response = api::router(state.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, body)
+}
+
+/// Records a connected call between two users, as if they had gone through
+/// invite and accept, so they may exchange signals.
+pub async fn connected_call(state: &AppState, caller_id: &str, callee_id: &str) {
+    let now = 1_700_000_000_000;
+    let call = CallRecord {
+        call_id: format!("{}-{}", caller_id, callee_id),
+        caller_id: caller_id.to_string(),
+        callee_id: callee_id.to_string(),
+        video: false,
+        status: CallStatus::Connected,
+        started_at: now,
+        answered_at: Some(now),
+        connected_at: Some(now),
+        ended_at: None,
+        ended_by: None,
+    };
+    state.calls.insert_call(&call).await.unwrap();
+}
+
+/// In-memory state with one group.
+pub fn state_with_group(group_id: &str, privacy: Privacy, members: &[&str]) -> AppState {
+    let store = Arc::new(MemoryStore::new());
+    store.put_group(Group {
+        group_id: group_id.to_string(),
+        members: members.iter().map(|m| m.to_string()).collect(),
+        privacy,
+    });
+    AppState::with_store(store)
+}
+
+/// In-memory state with the private group "rust" of alice, bob and carol.
+pub fn rust_group_state() -> AppState {
+    state_with_group("rust", Privacy::Private, &["alice", "bob", "carol"])
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::{
+    assert_silent, connect, get_json, recv_json, rust_group_state, send_json, spawn_hub,
+    wait_until, Client,
+};
+use realtime_hub::calls::CallConfig;
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+
+async fn enter_room(state: &AppState, client: &mut Client, user_id: &str) {
+    send_json(
+        client,
+        json!({ "type": "join_group", "user_id": user_id, "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &user_id.into())).await;
+}
+
+async fn conference(client: &mut Client, kind: &str) {
+    send_json(client, json!({ "type": kind, "group_id": "rust" })).await;
+}
+
+/// Next roster on each socket, which must all list `participants`.
+async fn expect_roster(clients: &mut [&mut Client], participants: Value) -> Value {
+    let mut last = Value::Null;
+    for client in clients.iter_mut() {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["type"], "conference_roster", "{}", frame);
+        assert_eq!(frame["participants"], participants, "{}", frame);
+        last = frame;
+    }
+    last
+}
+
+#[tokio::test]
+async fn participants_join_signal_pairwise_and_leave() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let mut carol = connect(addr, &state, "carol").await;
+    enter_room(&state, &mut alice, "alice").await;
+    enter_room(&state, &mut bob, "bob").await;
+    enter_room(&state, &mut carol, "carol").await;
+
+    conference(&mut alice, "conference_join").await;
+    let roster = expect_roster(&mut [&mut alice, &mut bob, &mut carol], json!(["alice"])).await;
+    assert_eq!(roster["joined"], "alice");
+    let call_id = roster["call_id"].clone();
+
+    conference(&mut bob, "conference_join").await;
+    let roster = expect_roster(
+        &mut [&mut alice, &mut bob, &mut carol],
+        json!(["alice", "bob"]),
+    )
+    .await;
+    assert_eq!(roster["call_id"], call_id);
+
+    send_json(
+        &mut bob,
+        json!({ "type": "conference_signal", "group_id": "rust", "target_id": "alice", "payload": { "type": "offer", "sdp": "v=0" } }),
+    )
+    .await;
+    let offer = recv_json(&mut alice).await;
+    assert_eq!(offer["type"], "conference_signal");
+    assert_eq!(offer["sender_id"], "bob");
+    assert_eq!(offer["call_id"], call_id);
+    assert_eq!(offer["payload"]["type"], "offer");
+    assert_silent(&mut carol).await;
+
+    // Carol is not in the call, so nobody can signal her through it.
+    send_json(
+        &mut alice,
+        json!({ "type": "conference_signal", "group_id": "rust", "target_id": "carol", "payload": {} }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["code"], "not_found");
+
+    conference(&mut bob, "conference_leave").await;
+    let roster = expect_roster(&mut [&mut alice, &mut bob, &mut carol], json!(["alice"])).await;
+    assert_eq!(roster["left"], "bob");
+    conference(&mut alice, "conference_leave").await;
+    let roster = expect_roster(&mut [&mut alice, &mut bob, &mut carol], json!([])).await;
+    assert_eq!(roster["ended"], true);
+
+    let (_, history) = get_json(&state, "bob", "/conferences").await;
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["call_id"], call_id);
+    assert!(history[0]["ended_at"].is_i64());
+    let participants = history[0]["participants"].as_array().unwrap();
+    let who: Vec<&str> = participants
+        .iter()
+        .map(|p| p["user_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(who, ["alice", "bob"]);
+    assert!(participants.iter().all(|p| p["left_at"].is_i64()));
+
+    let (_, history) = get_json(&state, "carol", "/conferences").await;
+    assert!(history.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn calls_are_capped_and_for_members_only() {
+    let state = rust_group_state().with_call_config(CallConfig {
+        max_participants: 2,
+        ..Default::default()
+    });
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let mut carol = connect(addr, &state, "carol").await;
+    let mut mallory = connect(addr, &state, "mallory").await;
+    enter_room(&state, &mut alice, "alice").await;
+    enter_room(&state, &mut bob, "bob").await;
+
+    // Carol is a member but has not entered the room.
+    conference(&mut carol, "conference_join").await;
+    assert_eq!(recv_json(&mut carol).await["code"], "forbidden");
+    conference(&mut mallory, "conference_join").await;
+    assert_eq!(recv_json(&mut mallory).await["code"], "forbidden");
+
+    enter_room(&state, &mut carol, "carol").await;
+    conference(&mut alice, "conference_join").await;
+    conference(&mut bob, "conference_join").await;
+    expect_roster(&mut [&mut carol], json!(["alice"])).await;
+    expect_roster(&mut [&mut carol], json!(["alice", "bob"])).await;
+
+    conference(&mut carol, "conference_join").await;
+    assert_eq!(recv_json(&mut carol).await["code"], "full");
+}
+
+#[tokio::test]
+async fn disconnecting_leaves_the_call() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    enter_room(&state, &mut alice, "alice").await;
+    enter_room(&state, &mut bob, "bob").await;
+
+    conference(&mut alice, "conference_join").await;
+    conference(&mut bob, "conference_join").await;
+    expect_roster(&mut [&mut alice], json!(["alice"])).await;
+    expect_roster(&mut [&mut alice], json!(["alice", "bob"])).await;
+
+    drop(bob);
+    let roster = loop {
+        let frame = recv_json(&mut alice).await;
+        if frame["type"] == "conference_roster" {
+            break frame;
+        }
+    };
+    assert_eq!(roster["participants"], json!(["alice"]));
+    assert_eq!(roster["left"], "bob");
+}
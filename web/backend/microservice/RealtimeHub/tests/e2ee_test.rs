@@ -0,0 +1,238 @@
+mod common;
+
+use axum::http::StatusCode;
+use base64::Engine;
+use common::{
+    assert_silent, connect, get_anonymous, get_json, recv_json, request_as, send_json, spawn_hub,
+};
+use realtime_hub::ratelimit::{Budget, RateLimitConfig};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+
+fn b64(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn key(seed: u8) -> String {
+    b64(&[seed; 32])
+}
+
+fn upload(seed: u8, prekey_ids: &[u32]) -> Value {
+    json!({
+        "identity_key": key(seed),
+        "signed_prekey": { "key_id": 1, "public_key": key(seed + 1), "signature": b64(&[seed; 64]) },
+        "one_time_prekeys": prekey_ids
+            .iter()
+            .map(|id| json!({ "key_id": id, "public_key": key(*id as u8) }))
+            .collect::<Vec<_>>()
+    })
+}
+
+#[tokio::test]
+async fn bundles_hand_out_each_prekey_once() {
+    let state = AppState::in_memory();
+
+    let (status, device) = request_as(
+        &state,
+        "bob",
+        "PUT",
+        "/devices/phone",
+        Some(upload(10, &[1, 2])),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(device["prekeys_left"], 2);
+    request_as(
+        &state,
+        "bob",
+        "PUT",
+        "/devices/laptop",
+        Some(upload(20, &[])),
+    )
+    .await;
+
+    let (status, _) = get_anonymous(&state, "/keys/bob").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let mut handed_out = Vec::new();
+    for _ in 0..3 {
+        let (status, bundle) = get_json(&state, "alice", "/keys/bob").await;
+        assert_eq!(status, StatusCode::OK);
+        let phone = bundle["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|d| d["device_id"] == "phone")
+            .unwrap()
+            .clone();
+        assert_eq!(phone["identity_key"], key(10));
+        assert_eq!(phone["signed_prekey"]["public_key"], key(11));
+        handed_out.push(phone["one_time_prekey"]["key_id"].clone());
+    }
+    assert_eq!(handed_out, [json!(1), json!(2), Value::Null]);
+
+    let (status, count) = request_as(
+        &state,
+        "bob",
+        "POST",
+        "/devices/phone/prekeys",
+        Some(json!({ "one_time_prekeys": [{ "key_id": 3, "public_key": key(3) }] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count["prekeys_left"], 1);
+
+    let (_, devices) = get_json(&state, "bob", "/devices").await;
+    assert_eq!(devices.as_array().unwrap().len(), 2);
+    assert!(devices[0].get("one_time_prekeys").is_none());
+
+    let (status, _) = request_as(&state, "bob", "DELETE", "/devices/laptop", None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, bundle) = get_json(&state, "alice", "/keys/bob").await;
+    assert_eq!(bundle["devices"].as_array().unwrap().len(), 1);
+
+    let (status, _) = get_json(&state, "alice", "/keys/carol").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn bundle_fetches_are_throttled() {
+    let state = AppState::in_memory().with_rate_limits(RateLimitConfig {
+        // Slow enough not to refill while the test runs.
+        key_bundle: Budget {
+            burst: 3.0,
+            per_second: 0.01,
+        },
+        peer_key_bundle: Budget {
+            burst: 2.0,
+            per_second: 0.01,
+        },
+        ..Default::default()
+    });
+    let prekeys: Vec<u32> = (1..=10).collect();
+    request_as(
+        &state,
+        "bob",
+        "PUT",
+        "/devices/phone",
+        Some(upload(10, &prekeys)),
+    )
+    .await;
+
+    for _ in 0..2 {
+        let (status, _) = get_json(&state, "alice", "/keys/bob").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = get_json(&state, "alice", "/keys/bob").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Bob's budget is shared; asking as someone else gets no more of his keys.
+    let (status, _) = get_json(&state, "mallory", "/keys/bob").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, devices) = get_json(&state, "bob", "/devices").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(devices[0]["prekeys_left"], 8);
+
+    // Other targets have their own budget, up to the requester's overall one.
+    let (status, _) = get_json(&state, "alice", "/keys/carol").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get_json(&state, "alice", "/keys/dave").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Alice's refused fetch took nothing from dave's budget.
+    for _ in 0..2 {
+        let (status, _) = get_json(&state, "mallory", "/keys/dave").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn refused_bundle_fetches_do_not_count_against_sockets() {
+    let state = AppState::in_memory().with_rate_limits(RateLimitConfig {
+        peer_key_bundle: Budget {
+            burst: 1.0,
+            per_second: 0.01,
+        },
+        max_violations: 2,
+        ..Default::default()
+    });
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    for _ in 0..4 {
+        get_json(&state, "alice", "/keys/bob").await;
+    }
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "still here", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+}
+
+#[tokio::test]
+async fn malformed_keys_are_refused() {
+    let state = AppState::in_memory();
+
+    let mut bad = upload(10, &[]);
+    bad["identity_key"] = json!("not base64!");
+    let (status, _) = request_as(&state, "bob", "PUT", "/devices/phone", Some(bad)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let mut oversized = upload(10, &[]);
+    oversized["signed_prekey"]["public_key"] = json!(b64(&[1; 4096]));
+    let (status, _) = request_as(&state, "bob", "PUT", "/devices/phone", Some(oversized)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = request_as(
+        &state,
+        "bob",
+        "POST",
+        "/devices/phone/prekeys",
+        Some(json!({ "one_time_prekeys": [] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn encrypted_messages_are_relayed_untouched() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    // Longer than any plaintext message may be.
+    let ciphertext = b64(&[0xA5; 6000]);
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": ciphertext, "kind": "encrypted" }),
+    )
+    .await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await;
+    let relayed = recv_json(&mut bob).await;
+    assert_eq!(relayed["kind"], "encrypted");
+    assert_eq!(relayed["content"], ciphertext);
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["messages"][0]["content"], ciphertext);
+
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": message_id, "content": b64(b"other") }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["code"], "unsupported");
+    assert_silent(&mut bob).await;
+
+    for frame in [
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "hello", "kind": "encrypted" }),
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": ciphertext, "kind": "encrypted" }),
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": ciphertext, "kind": "encrypted", "attachments": ["a.png"] }),
+    ] {
+        send_json(&mut alice, frame).await;
+        assert_eq!(recv_json(&mut alice).await["code"], "invalid");
+    }
+    assert_silent(&mut bob).await;
+}
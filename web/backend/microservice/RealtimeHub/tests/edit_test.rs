@@ -0,0 +1,152 @@
+mod common;
+
+use common::{assert_silent, connect, get_json, recv_json, send_json, spawn_hub, Client};
+use realtime_hub::store::{Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Sends a DM from alice to bob, drains the ack/echo/delivery and returns its id.
+async fn send_dm(alice: &mut Client, bob: &mut Client, content: &str) -> Value {
+    send_json(
+        alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": content, "kind": "text" }),
+    )
+    .await;
+    let message_id = recv_json(alice).await["message_id"].clone();
+    recv_json(alice).await; // echo
+    recv_json(bob).await;
+    message_id
+}
+
+#[tokio::test]
+async fn edits_keep_history_and_reach_both_sides() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let message_id = send_dm(&mut alice, &mut bob, "helo").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": message_id, "content": "hello" }),
+    )
+    .await;
+    for client in [&mut alice, &mut bob] {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["type"], "message_edited");
+        assert_eq!(frame["message_id"], message_id);
+        assert_eq!(frame["content"], "hello");
+    }
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    let message = &page["messages"][0];
+    assert_eq!(message["content"], "hello");
+    assert!(message["edited_at"].is_i64());
+    assert_eq!(message["edits"][0]["content"], "helo");
+    assert!(message["deleted_at"].is_null());
+}
+
+#[tokio::test]
+async fn delete_leaves_a_tombstone() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let message_id = send_dm(&mut alice, &mut bob, "oops").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "delete_message", "message_id": message_id }),
+    )
+    .await;
+    for client in [&mut alice, &mut bob] {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["type"], "message_deleted");
+        assert_eq!(frame["message_id"], message_id);
+    }
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    let message = &page["messages"][0];
+    assert_eq!(message["message_id"], message_id);
+    assert!(message["content"].is_null());
+    assert!(message["deleted_at"].is_i64());
+
+    // A tombstone cannot be edited back to life.
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": message_id, "content": "back" }),
+    )
+    .await;
+    assert_silent(&mut bob).await;
+}
+
+#[tokio::test]
+async fn only_the_sender_can_edit_or_delete() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let message_id = send_dm(&mut alice, &mut bob, "mine").await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "edit_message", "message_id": message_id, "content": "yours" }),
+    )
+    .await;
+    send_json(
+        &mut bob,
+        json!({ "type": "delete_message", "message_id": message_id }),
+    )
+    .await;
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "alice", "/messages?target_id=bob").await;
+    assert_eq!(page["messages"][0]["content"], "mine");
+}
+
+#[tokio::test]
+async fn removed_members_cannot_edit_their_group_messages() {
+    let store = Arc::new(MemoryStore::new());
+    let group = |members: &[&str]| Group {
+        group_id: "rust".to_string(),
+        members: members.iter().map(|m| m.to_string()).collect(),
+        privacy: Privacy::Private,
+    };
+    store.put_group(group(&["alice", "bob"]));
+    let state = AppState::with_store(store.clone());
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    common::wait_until(|| state.groups.get("rust").is_some_and(|room| room.len() == 2)).await;
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "mine", "kind": "text" }),
+    )
+    .await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await; // broadcast
+    recv_json(&mut bob).await;
+
+    // Social drops alice from the group.
+    store.put_group(group(&["bob"]));
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": message_id, "content": "rewritten" }),
+    )
+    .await;
+    let error = recv_json(&mut alice).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["code"], "forbidden");
+    assert_silent(&mut bob).await;
+
+    let (_, page) = get_json(&state, "bob", "/messages?group_id=rust").await;
+    assert_eq!(page["messages"][0]["content"], "mine");
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{
+    assert_silent, connect, get_json, recv_json, rust_group_state, send_json, spawn_hub,
+    state_with_group, wait_until,
+};
+use realtime_hub::store::Privacy;
+use serde_json::json;
+
+#[tokio::test]
+async fn outsiders_cannot_join_a_private_group() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut mallory = connect(addr, &state, "mallory").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    send_json(
+        &mut mallory,
+        json!({ "type": "join_group", "user_id": "mallory", "group_id": "rust" }),
+    )
+    .await;
+    let refusal = recv_json(&mut mallory).await;
+    assert_eq!(refusal["type"], "error");
+    assert_eq!(refusal["code"], "forbidden");
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+    assert!(!state.is_group_member("rust", &"mallory".into()));
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "members only", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+    assert_silent(&mut mallory).await;
+}
+
+#[tokio::test]
+async fn unknown_groups_cannot_be_joined() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "nope" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["code"], "forbidden");
+    assert!(state.groups.get("nope").is_none());
+}
+
+#[tokio::test]
+async fn public_groups_can_be_followed_but_only_members_post() {
+    let state = state_with_group("news", Privacy::Public, &["alice"]);
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "news" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("news").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "chat", "target_id": "news", "is_group": true, "content": "spam", "kind": "text", "client_msg_id": "c1" }),
+    )
+    .await;
+    let refusal = recv_json(&mut bob).await;
+    assert_eq!(refusal["type"], "error");
+    assert_eq!(refusal["client_msg_id"], "c1");
+    assert_silent(&mut alice).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "news", "is_group": true, "content": "headline", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut bob).await["content"], "headline");
+
+    let (_, history) = get_json(&state, "alice", "/messages?group_id=news").await;
+    assert_eq!(history["messages"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{connect, recv_json, send_json, spawn_hub, wait_until};
+use futures::{SinkExt, StreamExt};
+use realtime_hub::heartbeat::{HeartbeatConfig, HEARTBEAT_TIMEOUT, MIN_HEARTBEAT_INTERVAL};
+use realtime_hub::presence::{presence_of, Status};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+fn fast_heartbeat() -> AppState {
+    AppState::in_memory().with_heartbeat(HeartbeatConfig {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+    })
+}
+
+#[tokio::test]
+async fn silent_sessions_are_reaped() {
+    let state = fast_heartbeat();
+    let addr = spawn_hub(state.clone()).await;
+
+    // Never read from, so the client never answers the hub's pings.
+    let _alice = connect(addr, &state, "alice").await;
+    wait_until(|| !state.connections.is_online("alice")).await;
+
+    wait_until(|| {
+        futures::executor::block_on(presence_of(&state, &"alice".into()))
+            .last_seen
+            .is_some()
+    })
+    .await;
+    let presence = presence_of(&state, &"alice".into()).await;
+    assert_eq!(presence.status, Status::Offline);
+}
+
+#[tokio::test]
+async fn responsive_sessions_are_pinged_and_kept() {
+    let state = fast_heartbeat();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    // Reading lets the client answer every ping with a pong.
+    let mut pings = 0;
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(500);
+    while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, alice.next()).await {
+        if let Message::Ping(_) = msg.unwrap() {
+            pings += 1;
+        }
+    }
+    assert!(pings >= 3, "only {} pings", pings);
+    assert!(state.connections.is_online("alice"));
+}
+
+#[tokio::test]
+async fn binary_and_ping_frames_do_not_close_the_socket() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    alice.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    let error = recv_json(&mut alice).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["code"], "unsupported");
+
+    alice.send(Message::Ping(b"hi".to_vec())).await.unwrap();
+    let pong = tokio::time::timeout(Duration::from_secs(1), alice.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(pong, Message::Pong(b"hi".to_vec()));
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "still here", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+    assert!(state.connections.is_online("alice"));
+}
+
+#[test]
+fn zero_interval_is_clamped() {
+    let config = HeartbeatConfig::clamped(Duration::ZERO, HEARTBEAT_TIMEOUT);
+    assert_eq!(config.interval, MIN_HEARTBEAT_INTERVAL);
+    assert_eq!(config.timeout, HEARTBEAT_TIMEOUT);
+}
+
+#[test]
+fn timeout_must_outlast_the_interval() {
+    let interval = Duration::from_secs(30);
+    for timeout in [Duration::ZERO, Duration::from_secs(10), interval] {
+        let config = HeartbeatConfig::clamped(interval, timeout);
+        assert_eq!(config.interval, interval);
+        assert_eq!(config.timeout, interval * 2);
+    }
+
+    let config = HeartbeatConfig::clamped(interval, Duration::from_secs(31));
+    assert_eq!(config.timeout, Duration::from_secs(31));
+}
+
+#[test]
+fn env_values_are_clamped() {
+    std::env::set_var("HEARTBEAT_INTERVAL_SECS", "0");
+    std::env::set_var("HEARTBEAT_TIMEOUT_SECS", "0");
+    let config = HeartbeatConfig::from_env();
+    std::env::remove_var("HEARTBEAT_INTERVAL_SECS");
+    std::env::remove_var("HEARTBEAT_TIMEOUT_SECS");
+    assert_eq!(config.interval, MIN_HEARTBEAT_INTERVAL);
+    assert!(config.timeout > config.interval);
+}
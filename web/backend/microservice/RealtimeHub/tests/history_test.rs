@@ -0,0 +1,113 @@
+mod common;
+
+use common::get_json;
+use realtime_hub::store::ChatMessage;
+use realtime_hub::ws::AppState;
+use serde_json::Value;
+
+fn dm(id: &str, from: &str, to: &str, timestamp: i64) -> ChatMessage {
+    ChatMessage {
+        message_id: id.to_string(),
+        client_msg_id: None,
+        sender_id: from.to_string(),
+        target_id: to.to_string(),
+        is_group: false,
+        content: Some(format!("message {}", id)),
+        attachments: None,
+        kind: "text".to_string(),
+        timestamp,
+        ..Default::default()
+    }
+}
+
+fn ids(page: &Value) -> Vec<String> {
+    page["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["message_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Ten messages between alice and bob; "c".."f" share one timestamp.
+async fn seeded_state() -> AppState {
+    let state = AppState::in_memory();
+    let timestamps = [100, 200, 300, 300, 300, 300, 400, 500, 600, 700];
+    for (i, ts) in timestamps.iter().enumerate() {
+        let id = ((b'a' + i as u8) as char).to_string();
+        let (from, to) = if i % 2 == 0 {
+            ("alice", "bob")
+        } else {
+            ("bob", "alice")
+        };
+        state
+            .messages
+            .insert_message(&dm(&id, from, to, *ts))
+            .await
+            .unwrap();
+    }
+    // Noise from another conversation must never show up.
+    state
+        .messages
+        .insert_message(&dm("zz", "carol", "alice", 350))
+        .await
+        .unwrap();
+    state
+}
+
+#[tokio::test]
+async fn scrolls_back_with_before_cursor() {
+    let state = seeded_state().await;
+
+    let (status, page) = get_json(&state, "alice", "/messages?target_id=bob&limit=4").await;
+    assert!(status.is_success());
+    assert_eq!(ids(&page), ["g", "h", "i", "j"]);
+    let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+    let (_, page) = get_json(
+        &state,
+        "alice",
+        &format!("/messages?target_id=bob&limit=4&before={}", cursor),
+    )
+    .await;
+    assert_eq!(ids(&page), ["c", "d", "e", "f"]);
+    let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+    let (_, page) = get_json(
+        &state,
+        "alice",
+        &format!("/messages?target_id=bob&limit=4&before={}", cursor),
+    )
+    .await;
+    assert_eq!(ids(&page), ["a", "b"]);
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn pages_through_colliding_timestamps_without_gaps() {
+    let state = seeded_state().await;
+
+    let mut seen = Vec::new();
+    let mut uri = "/messages?target_id=alice&limit=3&after=0_".to_string();
+    loop {
+        let (_, page) = get_json(&state, "bob", &uri).await;
+        seen.extend(ids(&page));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("/messages?target_id=alice&limit=3&after={}", cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"]);
+}
+
+#[tokio::test]
+async fn rejects_malformed_cursor() {
+    let state = seeded_state().await;
+    let (status, _) = get_json(
+        &state,
+        "alice",
+        "/messages?target_id=bob&before=not-a-cursor",
+    )
+    .await;
+    assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+}
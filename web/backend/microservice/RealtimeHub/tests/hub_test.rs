@@ -0,0 +1,95 @@
+mod common;
+
+use common::{
+    connect, connected_call, get_json, recv_json, rust_group_state, send_json, spawn_hub,
+    wait_until,
+};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+
+#[tokio::test]
+async fn direct_message_is_delivered_and_persisted() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "hi bob", "kind": "text" }),
+    )
+    .await;
+
+    let received = recv_json(&mut bob).await;
+    assert_eq!(received["type"], "chat");
+    assert_eq!(received["sender_id"], "alice");
+    assert_eq!(received["content"], "hi bob");
+
+    let ack = recv_json(&mut alice).await;
+    assert_eq!(ack["type"], "ack");
+    assert_eq!(ack["message_id"], received["message_id"]);
+    let echo = recv_json(&mut alice).await;
+    assert_eq!(echo["content"], "hi bob");
+
+    let (status, history) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert!(status.is_success());
+    let history = history["messages"].as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["content"], "hi bob");
+    assert_eq!(history[0]["message_id"], received["message_id"]);
+}
+
+#[tokio::test]
+async fn group_chat_reaches_joined_members() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "hello group", "kind": "text" }),
+    )
+    .await;
+
+    assert_eq!(recv_json(&mut bob).await["content"], "hello group");
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+    assert_eq!(recv_json(&mut alice).await["content"], "hello group");
+
+    let (_, history) = get_json(&state, "bob", "/messages?group_id=rust").await;
+    assert_eq!(history["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn signals_are_relayed_as_they_are() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    connected_call(&state, "alice", "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "signal", "target_id": "bob", "payload": { "type": "bye" } }),
+    )
+    .await;
+    let relayed = recv_json(&mut bob).await;
+    assert_eq!(relayed["type"], "signal");
+    assert_eq!(relayed["sender_id"], "alice");
+    assert_eq!(relayed["payload"]["type"], "bye");
+
+    // Calls are recorded through the `call_*` frames, not from signal payloads.
+    let (_, page) = get_json(&state, "bob", "/calls").await;
+    let calls = page["calls"].as_array().unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0]["status"], "connected");
+}
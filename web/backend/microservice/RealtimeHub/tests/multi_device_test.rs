@@ -0,0 +1,95 @@
+mod common;
+
+use common::{connect, recv_json, rust_group_state, send_json, spawn_hub, wait_until};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+
+#[tokio::test]
+async fn every_device_of_a_user_gets_the_message() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut bob_desktop = connect(addr, &state, "bob").await;
+    let mut bob_web = connect(addr, &state, "bob").await;
+    assert_eq!(state.connections.session_count("bob"), 2);
+
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "hey", "kind": "text" }),
+    )
+    .await;
+
+    assert_eq!(recv_json(&mut bob_desktop).await["content"], "hey");
+    assert_eq!(recv_json(&mut bob_web).await["content"], "hey");
+}
+
+#[tokio::test]
+async fn sender_echo_reaches_the_other_devices() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice_desktop = connect(addr, &state, "alice").await;
+    let mut alice_web = connect(addr, &state, "alice").await;
+    let _bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice_desktop,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "sent from desktop", "kind": "text" }),
+    )
+    .await;
+
+    // Only the sending socket is acked; both sockets see the echo.
+    assert_eq!(recv_json(&mut alice_desktop).await["type"], "ack");
+    assert_eq!(
+        recv_json(&mut alice_desktop).await["content"],
+        "sent from desktop"
+    );
+    assert_eq!(
+        recv_json(&mut alice_web).await["content"],
+        "sent from desktop"
+    );
+}
+
+#[tokio::test]
+async fn closing_one_device_keeps_the_other_connected() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut bob_desktop = connect(addr, &state, "bob").await;
+    let mut bob_web = connect(addr, &state, "bob").await;
+
+    bob_desktop.close(None).await.unwrap();
+    wait_until(|| state.connections.session_count("bob") == 1).await;
+    assert!(state.connections.is_online("bob"));
+
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "still there?", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut bob_web).await["content"], "still there?");
+
+    bob_web.close(None).await.unwrap();
+    wait_until(|| !state.connections.is_online("bob")).await;
+}
+
+#[tokio::test]
+async fn group_membership_survives_until_the_last_session_closes() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut bob_desktop = connect(addr, &state, "bob").await;
+    let mut bob_web = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut bob_desktop,
+        json!({ "type": "join_group", "user_id": "bob", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"bob".into())).await;
+
+    bob_desktop.close(None).await.unwrap();
+    wait_until(|| state.connections.session_count("bob") == 1).await;
+    assert!(state.is_group_member("rust", &"bob".into()));
+
+    bob_web.close(None).await.unwrap();
+    wait_until(|| !state.is_group_member("rust", &"bob".into())).await;
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use common::{connect, connect_device, connect_raw, recv_json, send_json, spawn_hub, wait_until};
+use futures::StreamExt;
+use realtime_hub::bus::Route;
+use realtime_hub::protocol::ServerMessage;
+use realtime_hub::store::ChatMessage;
+use realtime_hub::ws::{AppState, DEFAULT_DEVICE};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn dm(to: &str, content: &str) -> Value {
+    json!({ "type": "chat", "target_id": to, "is_group": false, "content": content, "kind": "text" })
+}
+
+#[tokio::test]
+async fn missed_messages_are_replayed_on_connect() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    for text in ["one", "two"] {
+        send_json(&mut alice, dm("bob", text)).await;
+        recv_json(&mut alice).await; // ack
+        recv_json(&mut alice).await; // echo
+    }
+
+    let mut bob = connect_raw(addr, &state, "bob").await;
+    assert_eq!(recv_json(&mut bob).await["content"], "one");
+    assert_eq!(recv_json(&mut bob).await["content"], "two");
+    let marker = recv_json(&mut bob).await;
+    assert_eq!(marker["type"], "caught_up");
+    assert_eq!(marker["count"], 2);
+
+    // Live traffic resumes after the replay.
+    send_json(&mut alice, dm("bob", "three")).await;
+    assert_eq!(recv_json(&mut bob).await["content"], "three");
+}
+
+#[tokio::test]
+async fn delivered_moves_the_cursor() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    let mut ids = Vec::new();
+    for text in ["one", "two", "three"] {
+        send_json(&mut alice, dm("bob", text)).await;
+        ids.push(recv_json(&mut alice).await["message_id"].clone());
+        recv_json(&mut alice).await; // echo
+    }
+
+    let mut bob = connect_raw(addr, &state, "bob").await;
+    for _ in 0..4 {
+        recv_json(&mut bob).await;
+    }
+    send_json(
+        &mut bob,
+        json!({ "type": "delivered", "message_id": ids[1] }),
+    )
+    .await;
+    wait_until(|| {
+        futures::executor::block_on(state.messages.delivery_cursor("bob", DEFAULT_DEVICE))
+            .unwrap()
+            .is_some()
+    })
+    .await;
+    bob.close(None).await.unwrap();
+    wait_until(|| !state.connections.is_online("bob")).await;
+
+    // Only the message after the acknowledged one comes back.
+    let mut bob = connect_raw(addr, &state, "bob").await;
+    let replayed = recv_json(&mut bob).await;
+    assert_eq!(replayed["content"], "three");
+    assert_eq!(replayed["message_id"], ids[2]);
+    assert_eq!(recv_json(&mut bob).await["count"], 1);
+}
+
+#[tokio::test]
+async fn cannot_acknowledge_someone_elses_message() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut mallory = connect(addr, &state, "mallory").await;
+
+    send_json(&mut alice, dm("bob", "private")).await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+
+    send_json(
+        &mut mallory,
+        json!({ "type": "delivered", "message_id": message_id }),
+    )
+    .await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(state
+        .messages
+        .delivery_cursor("mallory", DEFAULT_DEVICE)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn a_message_both_replayed_and_delivered_live_arrives_once() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+
+    let message = ChatMessage {
+        message_id: "m1".to_string(),
+        sender_id: "alice".to_string(),
+        target_id: "bob".to_string(),
+        content: Some("one".to_string()),
+        kind: "text".to_string(),
+        timestamp: 1,
+        ..Default::default()
+    };
+    state.messages.insert_message(&message).await.unwrap();
+
+    // Stored before bob connected and delivered once he is registered, so the
+    // replay and the live route both carry it.
+    let mut bob = connect_raw(addr, &state, "bob").await;
+    let route = Route::User {
+        user_id: "bob".to_string(),
+    };
+    state
+        .deliver(route, ServerMessage::Chat(message).to_frame())
+        .await;
+
+    let mut frames = Vec::new();
+    while let Ok(Some(Ok(frame))) =
+        tokio::time::timeout(Duration::from_millis(150), bob.next()).await
+    {
+        frames.push(serde_json::from_str::<Value>(frame.to_text().unwrap()).unwrap());
+    }
+    let chats = frames.iter().filter(|f| f["type"] == "chat").count();
+    assert_eq!(chats, 1, "{:?}", frames);
+    assert!(frames.iter().any(|f| f["type"] == "caught_up"));
+
+    // Later live traffic still flows.
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(&mut alice, dm("bob", "two")).await;
+    assert_eq!(recv_json(&mut bob).await["content"], "two");
+}
+
+#[tokio::test]
+async fn each_device_catches_up_on_its_own() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(&mut alice, dm("bob", "one")).await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+
+    // The phone comes online and acknowledges the message.
+    let mut phone = connect_device(addr, &state, "bob", "phone").await;
+    assert_eq!(recv_json(&mut phone).await["content"], "one");
+    assert_eq!(recv_json(&mut phone).await["type"], "caught_up");
+    send_json(
+        &mut phone,
+        json!({ "type": "delivered", "message_id": message_id }),
+    )
+    .await;
+    wait_until(|| {
+        futures::executor::block_on(state.messages.delivery_cursor("bob", "phone"))
+            .unwrap()
+            .is_some()
+    })
+    .await;
+
+    // The laptop still gets it when it connects later.
+    let mut laptop = connect_device(addr, &state, "bob", "laptop").await;
+    let replayed = recv_json(&mut laptop).await;
+    assert_eq!(replayed["message_id"], message_id);
+    assert_eq!(recv_json(&mut laptop).await["count"], 1);
+}
+
+#[tokio::test]
+async fn a_new_device_gets_the_newest_messages() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    for i in 0..1005 {
+        let message = ChatMessage {
+            message_id: format!("m{:04}", i),
+            sender_id: "alice".to_string(),
+            target_id: "bob".to_string(),
+            content: Some(i.to_string()),
+            kind: "text".to_string(),
+            timestamp: i,
+            ..Default::default()
+        };
+        state.messages.insert_message(&message).await.unwrap();
+    }
+
+    // More than fit into one replay; the oldest ones are left out, not the newest.
+    let mut phone = connect_device(addr, &state, "bob", "phone").await;
+    assert_eq!(recv_json(&mut phone).await["message_id"], "m0005");
+    let mut last = Value::Null;
+    loop {
+        let frame = recv_json(&mut phone).await;
+        if frame["type"] == "caught_up" {
+            assert_eq!(frame["count"], 1000);
+            break;
+        }
+        last = frame;
+    }
+    assert_eq!(last["message_id"], "m1004");
+}
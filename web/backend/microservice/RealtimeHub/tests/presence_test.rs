@@ -0,0 +1,102 @@
+mod common;
+
+use common::{connect, get_json, recv_json, rust_group_state, send_json, spawn_hub, wait_until};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+
+#[tokio::test]
+async fn subscribers_see_connect_status_and_disconnect() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "subscribe_presence", "user_ids": ["bob"] }),
+    )
+    .await;
+    let initial = recv_json(&mut alice).await;
+    assert_eq!(initial["type"], "presence");
+    assert_eq!(initial["user_id"], "bob");
+    assert_eq!(initial["status"], "offline");
+
+    let mut bob = connect(addr, &state, "bob").await;
+    assert_eq!(recv_json(&mut alice).await["status"], "online");
+
+    send_json(&mut bob, json!({ "type": "set_status", "status": "dnd" })).await;
+    assert_eq!(recv_json(&mut alice).await["status"], "dnd");
+
+    bob.close(None).await.unwrap();
+    let offline = recv_json(&mut alice).await;
+    assert_eq!(offline["status"], "offline");
+    assert!(offline["last_seen"].as_i64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn group_co_members_see_presence_changes() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(&mut bob, json!({ "type": "set_status", "status": "away" })).await;
+    let away = recv_json(&mut alice).await;
+    assert_eq!(away["user_id"], "bob");
+    assert_eq!(away["status"], "away");
+}
+
+#[tokio::test]
+async fn second_device_does_not_repeat_online_and_first_close_is_not_offline() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "subscribe_presence", "user_ids": ["bob"] }),
+    )
+    .await;
+    recv_json(&mut alice).await;
+
+    let mut bob_desktop = connect(addr, &state, "bob").await;
+    assert_eq!(recv_json(&mut alice).await["status"], "online");
+    let _bob_web = connect(addr, &state, "bob").await;
+
+    bob_desktop.close(None).await.unwrap();
+    wait_until(|| state.connections.session_count("bob") == 1).await;
+    common::assert_silent(&mut alice).await;
+}
+
+#[tokio::test]
+async fn bulk_presence_lookup() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut bob = connect(addr, &state, "bob").await;
+    send_json(&mut bob, json!({ "type": "set_status", "status": "away" })).await;
+    let carol = connect(addr, &state, "carol").await;
+    drop(carol);
+    wait_until(|| !state.connections.is_online("carol")).await;
+    wait_until(|| {
+        futures::executor::block_on(realtime_hub::presence::presence_of(&state, &"bob".into()))
+            .status
+            == realtime_hub::presence::Status::Away
+    })
+    .await;
+
+    let (status, body) = get_json(&state, "alice", "/presence?user_ids=bob,carol,dave").await;
+    assert!(status.is_success());
+    let body = body.as_array().unwrap();
+    assert_eq!(body[0]["status"], "away");
+    assert_eq!(body[1]["status"], "offline");
+    assert!(body[1]["last_seen"].is_i64());
+    assert_eq!(body[2]["status"], "offline");
+    assert!(body[2]["last_seen"].is_null());
+}
@@ -0,0 +1,22 @@
+//! Snapshots of the exported protocol. A failure here means the wire format
+//! changed: if that was intended, review the diff with `cargo insta review`
+//! and tell the client teams.
+
+use realtime_hub::protocol;
+
+#[test]
+fn client_schema_is_unchanged() {
+    let schema = serde_json::to_string_pretty(&protocol::client_schema()).unwrap();
+    insta::assert_snapshot!("client_message.schema.json", schema);
+}
+
+#[test]
+fn server_schema_is_unchanged() {
+    let schema = serde_json::to_string_pretty(&protocol::server_schema()).unwrap();
+    insta::assert_snapshot!("server_message.schema.json", schema);
+}
+
+#[test]
+fn typescript_is_unchanged() {
+    insta::assert_snapshot!("protocol.ts", protocol::typescript());
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{assert_silent, connect, recv_json, rust_group_state, send_json, spawn_hub};
+use futures::SinkExt;
+use realtime_hub::protocol::{MAX_ATTACHMENTS, MAX_CONTENT_LEN};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+async fn expect_error(client: &mut common::Client, code: &str) -> Value {
+    let frame = recv_json(client).await;
+    assert_eq!(frame["type"], "error", "{}", frame);
+    assert_eq!(frame["code"], code, "{}", frame);
+    assert!(frame["message"].as_str().is_some_and(|m| !m.is_empty()));
+    frame
+}
+
+#[tokio::test]
+async fn unparsable_frames_are_answered() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    alice
+        .send(Message::Text("{not json".to_string()))
+        .await
+        .unwrap();
+    expect_error(&mut alice, "malformed").await;
+
+    send_json(&mut alice, json!({ "target_id": "bob" })).await;
+    expect_error(&mut alice, "malformed").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "shout", "client_msg_id": "c1" }),
+    )
+    .await;
+    let error = expect_error(&mut alice, "unknown_type").await;
+    assert_eq!(error["client_msg_id"], "c1");
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "client_msg_id": "c2" }),
+    )
+    .await;
+    let error = expect_error(&mut alice, "invalid").await;
+    assert_eq!(error["client_msg_id"], "c2");
+}
+
+#[tokio::test]
+async fn chats_out_of_bounds_are_refused() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    let refused = [
+        json!({ "content": "x".repeat(MAX_CONTENT_LEN + 1), "kind": "text" }),
+        json!({ "attachments": vec!["a.png"; MAX_ATTACHMENTS + 1], "kind": "image" }),
+        json!({ "content": "hi", "kind": "hologram" }),
+        json!({ "content": "", "kind": "text" }),
+    ];
+    for (n, mut chat) in refused.into_iter().enumerate() {
+        chat["type"] = "chat".into();
+        chat["target_id"] = "bob".into();
+        chat["is_group"] = false.into();
+        chat["client_msg_id"] = format!("c{}", n).into();
+        send_json(&mut alice, chat).await;
+        let error = expect_error(&mut alice, "invalid").await;
+        assert_eq!(error["client_msg_id"], format!("c{}", n));
+    }
+    assert_silent(&mut bob).await;
+
+    // At the limit is fine, counted in characters rather than bytes.
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "é".repeat(MAX_CONTENT_LEN), "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+    assert_eq!(recv_json(&mut bob).await["type"], "chat");
+}
+
+#[tokio::test]
+async fn spoofed_user_ids_are_forbidden() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "bob", "group_id": "rust" }),
+    )
+    .await;
+    expect_error(&mut alice, "forbidden").await;
+    assert!(!state.groups.contains_key("rust"));
+
+    send_json(&mut alice, json!({ "type": "join", "user_id": "alice" })).await;
+    assert_silent(&mut alice).await;
+}
+
+#[tokio::test]
+async fn edits_of_other_peoples_messages_are_not_found() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": "nope", "content": "x" }),
+    )
+    .await;
+    expect_error(&mut alice, "not_found").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "edit_message", "message_id": "nope" }),
+    )
+    .await;
+    expect_error(&mut alice, "invalid").await;
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::{connect, connected_call, recv_json, send_json, spawn_hub, wait_until};
+use futures::{SinkExt, StreamExt};
+use realtime_hub::protocol::MAX_PRESENCE_SUBSCRIPTIONS;
+use realtime_hub::ratelimit::{Budget, LocalBuckets, RateLimitConfig};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+fn limited(max_violations: u32) -> AppState {
+    AppState::in_memory().with_rate_limits(RateLimitConfig {
+        chat: Budget::new(3, 1),
+        signal: Budget::new(5, 1),
+        typing: Budget::new(1, 1),
+        control: Budget::new(2, 1),
+        key_bundle: Budget::new(3, 1),
+        peer_key_bundle: Budget::new(2, 1),
+        max_violations,
+    })
+}
+
+fn chat(n: u32) -> Value {
+    json!({
+        "type": "chat",
+        "target_id": "bob",
+        "is_group": false,
+        "content": format!("message {}", n),
+        "kind": "text",
+        "client_msg_id": format!("c{}", n)
+    })
+}
+
+#[tokio::test]
+async fn chats_over_budget_are_refused_with_retry_after() {
+    let state = limited(100);
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    for n in 0..3 {
+        send_json(&mut alice, chat(n)).await;
+        assert_eq!(recv_json(&mut alice).await["type"], "ack");
+        recv_json(&mut alice).await; // echo
+    }
+    send_json(&mut alice, chat(3)).await;
+    let refusal = recv_json(&mut alice).await;
+    assert_eq!(refusal["type"], "error");
+    assert_eq!(refusal["code"], "rate_limited");
+    assert_eq!(refusal["client_msg_id"], "c3");
+    let retry_after = refusal["retry_after_ms"].as_u64().unwrap();
+    assert!(retry_after > 0 && retry_after <= 1000);
+
+    // Signals have a budget of their own.
+    connected_call(&state, "alice", "bob").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "signal", "target_id": "bob", "payload": { "type": "offer" } }),
+    )
+    .await;
+    send_json(
+        &mut alice,
+        json!({ "type": "delivered", "message_id": "none" }),
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(retry_after + 50)).await;
+    send_json(&mut alice, chat(4)).await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+}
+
+#[tokio::test]
+async fn repeated_abuse_disconnects() {
+    let state = limited(3);
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    for _ in 0..4 {
+        send_json(
+            &mut alice,
+            json!({ "type": "typing", "target_id": "bob", "is_group": false }),
+        )
+        .await;
+    }
+    wait_until(|| !state.connections.is_online("alice")).await;
+
+    let mut refusals = 0;
+    let close = loop {
+        match tokio::time::timeout(Duration::from_secs(1), alice.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                let frame: Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(frame["code"], "rate_limited");
+                refusals += 1;
+            }
+            Ok(Some(Ok(Message::Close(close)))) => break close,
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    };
+    assert_eq!(refusals, 2);
+    assert_eq!(close.unwrap().code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn budgets_are_shared_between_nodes() {
+    let buckets = Arc::new(LocalBuckets::new());
+    let node_a = limited(100).with_rate_buckets(buckets.clone());
+    let node_b = limited(100).with_rate_buckets(buckets);
+    let addr_a = spawn_hub(node_a.clone()).await;
+    let addr_b = spawn_hub(node_b.clone()).await;
+
+    let mut alice_a = connect(addr_a, &node_a, "alice").await;
+    for n in 0..3 {
+        send_json(&mut alice_a, chat(n)).await;
+        assert_eq!(recv_json(&mut alice_a).await["type"], "ack");
+        recv_json(&mut alice_a).await; // echo
+    }
+
+    // Moving to another node does not come with a fresh budget.
+    let mut alice_b = connect(addr_b, &node_b, "alice").await;
+    send_json(&mut alice_b, chat(3)).await;
+    assert_eq!(recv_json(&mut alice_b).await["code"], "rate_limited");
+}
+
+#[tokio::test]
+async fn every_frame_has_a_budget() {
+    let state = limited(100);
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    for _ in 0..2 {
+        send_json(
+            &mut alice,
+            json!({ "type": "set_status", "status": "away" }),
+        )
+        .await;
+    }
+    send_json(
+        &mut alice,
+        json!({ "type": "subscribe_presence", "user_ids": ["bob"] }),
+    )
+    .await;
+    let refusal = recv_json(&mut alice).await;
+    assert_eq!(refusal["code"], "rate_limited");
+    assert_eq!(refusal["message"], "too many control frames");
+}
+
+#[tokio::test]
+async fn flooding_with_invalid_frames_disconnects() {
+    let state = limited(3);
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    let garbage = [
+        Message::Text("not json".to_string()),
+        Message::Text(json!({ "type": "no_such_frame" }).to_string()),
+        Message::Binary(vec![1, 2, 3]),
+    ];
+    for frame in garbage.iter().cycle().take(6) {
+        alice.send(frame.clone()).await.unwrap();
+    }
+    wait_until(|| !state.connections.is_online("alice")).await;
+
+    let close = loop {
+        match tokio::time::timeout(Duration::from_secs(1), alice.next()).await {
+            Ok(Some(Ok(Message::Text(_)))) => {}
+            Ok(Some(Ok(Message::Close(close)))) => break close,
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    };
+    assert_eq!(close.unwrap().code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn oversized_presence_subscriptions_are_refused() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+
+    let user_ids: Vec<String> = (0..=MAX_PRESENCE_SUBSCRIPTIONS)
+        .map(|n| format!("user{}", n))
+        .collect();
+    send_json(
+        &mut alice,
+        json!({ "type": "subscribe_presence", "user_ids": user_ids }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["code"], "invalid");
+    common::assert_silent(&mut alice).await;
+}
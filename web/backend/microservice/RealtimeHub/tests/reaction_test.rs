@@ -0,0 +1,109 @@
+mod common;
+
+use common::{
+    assert_silent, connect, get_json, recv_json, rust_group_state, send_json, spawn_hub, wait_until,
+};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+
+#[tokio::test]
+async fn reactions_are_aggregated_per_emoji() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "hi", "kind": "text" }),
+    )
+    .await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await; // echo
+    recv_json(&mut bob).await;
+
+    for (user, emoji) in [("bob", "👍"), ("alice", "👍"), ("bob", "🎉")] {
+        let client = if user == "bob" { &mut bob } else { &mut alice };
+        send_json(
+            client,
+            json!({ "type": "react", "message_id": message_id, "emoji": emoji }),
+        )
+        .await;
+        recv_json(&mut alice).await;
+        recv_json(&mut bob).await;
+    }
+
+    // Reacting twice with the same emoji changes nothing.
+    send_json(
+        &mut bob,
+        json!({ "type": "react", "message_id": message_id, "emoji": "👍" }),
+    )
+    .await;
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "alice", "/messages?target_id=bob").await;
+    let reactions = &page["messages"][0]["reactions"];
+    assert_eq!(reactions["👍"], json!(["bob", "alice"]));
+    assert_eq!(reactions["🎉"], json!(["bob"]));
+
+    send_json(
+        &mut bob,
+        json!({ "type": "unreact", "message_id": message_id, "emoji": "🎉" }),
+    )
+    .await;
+    let frame = recv_json(&mut alice).await;
+    assert_eq!(frame["type"], "reaction");
+    assert_eq!(frame["user_id"], "bob");
+    assert_eq!(frame["added"], false);
+    assert_eq!(frame["reactors"], json!([]));
+
+    let (_, page) = get_json(&state, "alice", "/messages?target_id=bob").await;
+    let reactions = &page["messages"][0]["reactions"];
+    assert!(reactions.get("🎉").is_none());
+}
+
+#[tokio::test]
+async fn group_reactions_reach_members_only() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let mut mallory = connect(addr, &state, "mallory").await;
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "hi", "kind": "text" }),
+    )
+    .await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await; // echo
+    recv_json(&mut bob).await;
+
+    send_json(
+        &mut mallory,
+        json!({ "type": "react", "message_id": message_id, "emoji": "👎" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut mallory).await["code"], "forbidden");
+    assert_silent(&mut alice).await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "react", "message_id": message_id, "emoji": "❤️" }),
+    )
+    .await;
+    for client in [&mut alice, &mut bob] {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["emoji"], "❤️");
+        assert_eq!(frame["reactors"], json!(["bob"]));
+    }
+    assert_silent(&mut mallory).await;
+}
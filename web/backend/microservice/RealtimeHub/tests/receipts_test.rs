@@ -0,0 +1,125 @@
+mod common;
+
+use common::{
+    assert_silent, connect, get_json, recv_json, rust_group_state, send_json, spawn_hub, wait_until,
+};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+
+fn dm(to: &str, content: &str) -> Value {
+    json!({ "type": "chat", "target_id": to, "is_group": false, "content": content, "kind": "text" })
+}
+
+#[tokio::test]
+async fn typing_is_relayed_throttled_and_not_stored() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    let typing = json!({ "type": "typing", "target_id": "bob", "is_group": false });
+    send_json(&mut alice, typing.clone()).await;
+    let frame = recv_json(&mut bob).await;
+    assert_eq!(frame["type"], "typing");
+    assert_eq!(frame["sender_id"], "alice");
+
+    // A burst inside the throttle window is swallowed, and the sender gets no echo.
+    send_json(&mut alice, typing.clone()).await;
+    send_json(&mut alice, typing).await;
+    assert_silent(&mut bob).await;
+    assert_silent(&mut alice).await;
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert!(page["messages"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn group_typing_skips_the_typist() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    for (client, user) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "typing", "target_id": "rust", "is_group": true }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut bob).await["type"], "typing");
+    assert_silent(&mut alice).await;
+}
+
+#[tokio::test]
+async fn read_receipts_are_persisted_and_broadcast() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    let mut ids = Vec::new();
+    for text in ["one", "two", "three"] {
+        send_json(&mut alice, dm("bob", text)).await;
+        ids.push(recv_json(&mut alice).await["message_id"].clone());
+        recv_json(&mut alice).await; // echo
+        recv_json(&mut bob).await;
+    }
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["unread_count"], 3);
+
+    send_json(
+        &mut bob,
+        json!({ "type": "read", "target_id": "alice", "is_group": false, "message_id": ids[1] }),
+    )
+    .await;
+    let receipt = recv_json(&mut alice).await;
+    assert_eq!(receipt["type"], "read");
+    assert_eq!(receipt["user_id"], "bob");
+    assert_eq!(receipt["message_id"], ids[1]);
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["unread_count"], 1);
+
+    // Alice sees how far bob has read; her own messages never count as unread.
+    let (_, page) = get_json(&state, "alice", "/messages?target_id=bob").await;
+    assert_eq!(page["unread_count"], 0);
+    assert_eq!(page["read_by"][0]["user_id"], "bob");
+    assert_eq!(page["read_by"][0]["message_id"], ids[1]);
+
+    // Reading an older message never moves the cursor back.
+    send_json(
+        &mut bob,
+        json!({ "type": "read", "target_id": "alice", "is_group": false, "message_id": ids[0] }),
+    )
+    .await;
+    recv_json(&mut alice).await;
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["unread_count"], 1);
+}
+
+#[tokio::test]
+async fn read_outside_the_conversation_is_ignored() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut mallory = connect(addr, &state, "mallory").await;
+
+    send_json(&mut alice, dm("bob", "private")).await;
+    let message_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await; // echo
+
+    send_json(
+        &mut mallory,
+        json!({ "type": "read", "target_id": "alice", "is_group": false, "message_id": message_id }),
+    )
+    .await;
+    assert_silent(&mut alice).await;
+}
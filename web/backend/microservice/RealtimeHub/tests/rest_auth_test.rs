@@ -0,0 +1,115 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{
+    connect, get_anonymous, get_json, rust_group_state, send_json, spawn_hub, wait_until,
+};
+use realtime_hub::store::{ChatMessage, Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+use std::sync::Arc;
+
+fn message(id: &str, from: &str, to: &str, is_group: bool) -> ChatMessage {
+    ChatMessage {
+        message_id: id.to_string(),
+        client_msg_id: None,
+        sender_id: from.to_string(),
+        target_id: to.to_string(),
+        is_group,
+        content: Some("secret".to_string()),
+        attachments: None,
+        kind: "text".to_string(),
+        timestamp: 1,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn rest_routes_require_a_bearer_token() {
+    let state = AppState::in_memory();
+
+    let (status, _) = get_anonymous(&state, "/messages?target_id=bob").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = get_anonymous(&state, "/calls").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn caller_comes_from_the_token_not_the_query() {
+    let state = AppState::in_memory();
+    state
+        .messages
+        .insert_message(&message("1", "alice", "bob", false))
+        .await
+        .unwrap();
+
+    // Mallory asking for "alice <-> bob" only ever sees "mallory <-> bob".
+    let (status, page) = get_json(&state, "mallory", "/messages?user_id=alice&target_id=bob").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page["messages"].as_array().unwrap().is_empty());
+
+    let (_, page) = get_json(&state, "bob", "/messages?target_id=alice").await;
+    assert_eq!(page["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn history_without_conversation_is_rejected() {
+    let state = AppState::in_memory();
+    let (status, _) = get_json(&state, "alice", "/messages").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn group_history_is_limited_to_members() {
+    let state = rust_group_state();
+    state
+        .messages
+        .insert_message(&message("1", "alice", "rust", true))
+        .await
+        .unwrap();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.groups.contains_key("rust")).await;
+
+    let (status, page) = get_json(&state, "alice", "/messages?group_id=rust").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["messages"].as_array().unwrap().len(), 1);
+
+    let (status, _) = get_json(&state, "mallory", "/messages?group_id=rust").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn removed_members_lose_group_history() {
+    let store = Arc::new(MemoryStore::new());
+    let group = |members: &[&str]| Group {
+        group_id: "rust".to_string(),
+        members: members.iter().map(|m| m.to_string()).collect(),
+        privacy: Privacy::Private,
+    };
+    store.put_group(group(&["alice", "bob"]));
+    let state = AppState::with_store(store.clone());
+    state
+        .messages
+        .insert_message(&message("1", "alice", "rust", true))
+        .await
+        .unwrap();
+    state.rooms.join_room("rust", "bob").await.unwrap();
+
+    let (status, _) = get_json(&state, "bob", "/messages?group_id=rust").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = get_json(&state, "bob", "/threads/1").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Social drops bob from the group; his room membership is still on record.
+    store.put_group(group(&["alice"]));
+    let (status, _) = get_json(&state, "bob", "/messages?group_id=rust").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = get_json(&state, "bob", "/threads/1").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{connect, get_json, recv_json, rust_group_state, send_json, spawn_hub, wait_until};
+use realtime_hub::store::{Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn joined_rooms_are_restored_on_reconnect() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+
+    alice.close(None).await.unwrap();
+    wait_until(|| !state.is_group_member("rust", &"alice".into())).await;
+
+    // Offline, she is still in the room as far as history goes.
+    let (status, _) = get_json(&state, "alice", "/messages?group_id=rust").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, rooms) = get_json(&state, "alice", "/rooms").await;
+    assert_eq!(rooms, json!(["rust"]));
+
+    let mut alice = connect(addr, &state, "alice").await;
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+
+    let mut bob = connect(addr, &state, "bob").await;
+    send_json(
+        &mut bob,
+        json!({ "type": "chat", "target_id": "rust", "is_group": true, "content": "welcome back", "kind": "text" }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut alice).await["content"], "welcome back");
+}
+
+#[tokio::test]
+async fn leaving_is_durable() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+    send_json(
+        &mut alice,
+        json!({ "type": "leave_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| !state.is_group_member("rust", &"alice".into())).await;
+    alice.close(None).await.unwrap();
+    wait_until(|| !state.connections.is_online("alice")).await;
+
+    let _alice = connect(addr, &state, "alice").await;
+    let (_, rooms) = get_json(&state, "alice", "/rooms").await;
+    assert_eq!(rooms, json!([]));
+    assert!(!state.is_group_member("rust", &"alice".into()));
+}
+
+#[tokio::test]
+async fn rooms_of_groups_left_in_social_are_dropped() {
+    let store = Arc::new(MemoryStore::new());
+    let group = |members: &[&str]| Group {
+        group_id: "rust".to_string(),
+        members: members.iter().map(|m| m.to_string()).collect(),
+        privacy: Privacy::Private,
+    };
+    store.put_group(group(&["alice", "bob"]));
+    let state = AppState::with_store(store.clone());
+    let addr = spawn_hub(state.clone()).await;
+
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+    alice.close(None).await.unwrap();
+    wait_until(|| !state.connections.is_online("alice")).await;
+
+    store.put_group(group(&["bob"]));
+    let _alice = connect(addr, &state, "alice").await;
+    let (_, rooms) = get_json(&state, "alice", "/rooms").await;
+    assert_eq!(rooms, json!([]));
+    assert!(!state.is_group_member("rust", &"alice".into()));
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{
+    assert_silent, connect, get_json, recv_json, rust_group_state, send_json, spawn_hub, wait_until,
+};
+use realtime_hub::store::{Group, MemoryStore, Privacy};
+use realtime_hub::ws::AppState;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn group_chat(content: &str, thread_root: Option<&Value>) -> Value {
+    json!({
+        "type": "chat",
+        "target_id": "rust",
+        "is_group": true,
+        "content": content,
+        "kind": "text",
+        "thread_root": thread_root
+    })
+}
+
+#[tokio::test]
+async fn thread_replies_reach_participants_outside_the_room() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+    let mut carol = connect(addr, &state, "carol").await;
+    for (client, user) in [
+        (&mut alice, "alice"),
+        (&mut bob, "bob"),
+        (&mut carol, "carol"),
+    ] {
+        send_json(
+            client,
+            json!({ "type": "join_group", "user_id": user, "group_id": "rust" }),
+        )
+        .await;
+    }
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 3)).await;
+
+    send_json(&mut carol, group_chat("how do lifetimes work?", None)).await;
+    let root_id = recv_json(&mut carol).await["message_id"].clone();
+    for client in [&mut alice, &mut bob, &mut carol] {
+        recv_json(client).await;
+    }
+
+    send_json(
+        &mut carol,
+        json!({ "type": "leave_group", "user_id": "carol", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.groups.get("rust").is_some_and(|m| m.len() == 2)).await;
+
+    send_json(&mut alice, group_chat("read the book", Some(&root_id))).await;
+    recv_json(&mut alice).await; // ack
+    for client in [&mut alice, &mut bob] {
+        let frame = recv_json(client).await;
+        assert_eq!(frame["type"], "chat");
+        assert_eq!(frame["thread_root"], root_id);
+    }
+    let notice = recv_json(&mut carol).await;
+    assert_eq!(notice["type"], "thread_reply");
+    assert_eq!(notice["sender_id"], "alice");
+    assert_eq!(notice["thread_root"], root_id);
+
+    let uri = format!("/threads/{}", root_id.as_str().unwrap());
+    let (status, page) = get_json(&state, "carol", &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["root"]["reply_count"], 1);
+    assert_eq!(page["replies"][0]["content"], "read the book");
+
+    let (status, _) = get_json(&state, "mallory", &uri).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn removed_members_get_no_thread_replies() {
+    let store = Arc::new(MemoryStore::new());
+    let group = |members: &[&str]| Group {
+        group_id: "rust".to_string(),
+        members: members.iter().map(|m| m.to_string()).collect(),
+        privacy: Privacy::Private,
+    };
+    store.put_group(group(&["alice", "carol"]));
+    let state = AppState::with_store(store.clone());
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut carol = connect(addr, &state, "carol").await;
+    send_json(
+        &mut carol,
+        json!({ "type": "join_group", "user_id": "carol", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"carol".into())).await;
+    send_json(&mut carol, group_chat("how do lifetimes work?", None)).await;
+    let root_id = recv_json(&mut carol).await["message_id"].clone();
+    recv_json(&mut carol).await;
+    send_json(
+        &mut carol,
+        json!({ "type": "leave_group", "user_id": "carol", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| !state.is_group_member("rust", &"carol".into())).await;
+
+    // Social drops carol from the group before anyone answers.
+    store.put_group(group(&["alice"]));
+    send_json(&mut alice, group_chat("read the book", Some(&root_id))).await;
+    assert_eq!(recv_json(&mut alice).await["type"], "ack");
+    assert_silent(&mut carol).await;
+}
+
+#[tokio::test]
+async fn thread_pages_walk_forward() {
+    let state = rust_group_state();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    send_json(
+        &mut alice,
+        json!({ "type": "join_group", "user_id": "alice", "group_id": "rust" }),
+    )
+    .await;
+    wait_until(|| state.is_group_member("rust", &"alice".into())).await;
+
+    send_json(&mut alice, group_chat("root", None)).await;
+    let root_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await;
+    for i in 0..3 {
+        send_json(
+            &mut alice,
+            group_chat(&format!("reply {}", i), Some(&root_id)),
+        )
+        .await;
+        recv_json(&mut alice).await;
+        recv_json(&mut alice).await;
+    }
+
+    let uri = format!("/threads/{}?limit=2", root_id.as_str().unwrap());
+    let (_, page) = get_json(&state, "alice", &uri).await;
+    assert_eq!(page["root"]["reply_count"], 3);
+    assert_eq!(page["replies"].as_array().unwrap().len(), 2);
+    let next = page["next_cursor"].as_str().unwrap();
+
+    let uri = format!("{}&after={}", uri, next);
+    let (_, page) = get_json(&state, "alice", &uri).await;
+    assert_eq!(page["replies"][0]["content"], "reply 2");
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn references_must_stay_in_the_conversation() {
+    let state = AppState::in_memory();
+    let addr = spawn_hub(state.clone()).await;
+    let mut alice = connect(addr, &state, "alice").await;
+    let mut bob = connect(addr, &state, "bob").await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "carol", "is_group": false, "content": "psst", "kind": "text" }),
+    )
+    .await;
+    let foreign_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "chat", "target_id": "alice", "is_group": false, "content": "re", "kind": "text", "reply_to": foreign_id }),
+    )
+    .await;
+    let refusal = recv_json(&mut bob).await;
+    assert_eq!(refusal["type"], "error");
+    assert_eq!(refusal["code"], "invalid");
+    assert_silent(&mut bob).await;
+    assert_silent(&mut alice).await;
+
+    send_json(
+        &mut alice,
+        json!({ "type": "chat", "target_id": "bob", "is_group": false, "content": "question", "kind": "text" }),
+    )
+    .await;
+    let question_id = recv_json(&mut alice).await["message_id"].clone();
+    recv_json(&mut alice).await;
+    recv_json(&mut bob).await;
+
+    send_json(
+        &mut bob,
+        json!({ "type": "chat", "target_id": "alice", "is_group": false, "content": "answer", "kind": "text", "reply_to": question_id }),
+    )
+    .await;
+    assert_eq!(recv_json(&mut bob).await["type"], "ack");
+    let answer = recv_json(&mut alice).await;
+    assert_eq!(answer["reply_to"], question_id);
+}
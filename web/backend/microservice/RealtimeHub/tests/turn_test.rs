@@ -0,0 +1,228 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{get_anonymous, get_json};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use realtime_hub::ice::{turn_credential, IceConfig, MAX_TURN_TTL};
+use realtime_hub::ws::AppState;
+use serde_json::json;
+use sha1::Sha1;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+fn ice_state(secret: Option<&str>) -> AppState {
+    AppState::in_memory().with_ice_config(IceConfig {
+        stun_urls: vec!["stun:stun.example.com:3478".to_string()],
+        turn_urls: vec![
+            "turn:turn.example.com:3478?transport=udp".to_string(),
+            "turns:turn.example.com:5349?transport=tcp".to_string(),
+        ],
+        turn_secret: secret.map(str::to_string),
+        ttl: Duration::from_secs(600),
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn credentials_follow_the_turn_rest_api() {
+    // base64(HMAC-SHA1("north", "1700086400:alice")), as coturn computes it.
+    assert_eq!(
+        turn_credential("north", "1700086400:alice"),
+        "SXua5ne/+mDhiHTp0pQJzRO4ESg="
+    );
+}
+
+#[test]
+fn huge_ttls_do_not_overflow() {
+    let config = IceConfig {
+        turn_urls: vec!["turn:turn.example.com:3478".to_string()],
+        turn_secret: Some("north".to_string()),
+        ttl: Duration::MAX,
+        ..Default::default()
+    };
+    let servers = config.servers_for(&"alice".into(), unix_now());
+    assert_eq!(servers.expires_at, u64::MAX);
+
+    std::env::set_var("TURN_TTL_SECS", u64::MAX.to_string());
+    let config = IceConfig::from_env();
+    std::env::remove_var("TURN_TTL_SECS");
+    assert_eq!(config.ttl, MAX_TURN_TTL);
+}
+
+#[tokio::test]
+async fn turn_credentials_are_issued_per_user() {
+    let state = ice_state(Some("north"));
+
+    let (status, _) = get_anonymous(&state, "/ice-servers").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let before = unix_now();
+    let (status, body) = get_json(&state, "alice", "/ice-servers").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ttl"], 600);
+    let servers = body["ice_servers"].as_array().unwrap();
+    assert_eq!(servers.len(), 2);
+    assert_eq!(
+        servers[0],
+        json!({ "urls": ["stun:stun.example.com:3478"] })
+    );
+
+    let turn = &servers[1];
+    assert_eq!(turn["urls"].as_array().unwrap().len(), 2);
+    let username = turn["username"].as_str().unwrap();
+    let (expiry, user_id) = username.split_once(':').unwrap();
+    assert_eq!(user_id, "alice");
+    let expiry: u64 = expiry.parse().unwrap();
+    assert!(expiry >= before + 600 && expiry <= unix_now() + 600);
+    assert_eq!(body["expires_at"], expiry);
+    assert_eq!(turn["credential"], turn_credential("north", username));
+}
+
+#[tokio::test]
+async fn no_turn_servers_without_a_secret() {
+    let state = ice_state(None);
+    let (status, body) = get_json(&state, "alice", "/ice-servers").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["ice_servers"],
+        json!([{ "urls": ["stun:stun.example.com:3478"] }])
+    );
+}
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const ALLOCATE: u16 = 0x0003;
+const REFRESH: u16 = 0x0004;
+const USERNAME: u16 = 0x0006;
+const MESSAGE_INTEGRITY: u16 = 0x0008;
+const ERROR_CODE: u16 = 0x0009;
+const LIFETIME: u16 = 0x000D;
+const REALM: u16 = 0x0014;
+const NONCE: u16 = 0x0015;
+const REQUESTED_TRANSPORT: u16 = 0x0019;
+
+/// A STUN request with the attributes in order, signed with `key` if given.
+fn stun_request(method: u16, attributes: &[(u16, Vec<u8>)], key: Option<&[u8]>) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (kind, value) in attributes {
+        body.extend_from_slice(&kind.to_be_bytes());
+        body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        body.extend_from_slice(value);
+        body.resize(body.len().next_multiple_of(4), 0);
+    }
+    let signed_len = body.len() + if key.is_some() { 24 } else { 0 };
+    let mut message = Vec::new();
+    message.extend_from_slice(&method.to_be_bytes());
+    message.extend_from_slice(&(signed_len as u16).to_be_bytes());
+    message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    message.extend_from_slice(&uuid::Uuid::new_v4().as_bytes()[..12]);
+    message.extend_from_slice(&body);
+    if let Some(key) = key {
+        let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+        mac.update(&message);
+        message.extend_from_slice(&MESSAGE_INTEGRITY.to_be_bytes());
+        message.extend_from_slice(&20u16.to_be_bytes());
+        message.extend_from_slice(&mac.finalize().into_bytes());
+    }
+    message
+}
+
+/// Sends a request and returns the response class bits and attributes.
+async fn exchange(socket: &UdpSocket, request: &[u8]) -> (u16, Vec<(u16, Vec<u8>)>) {
+    socket.send(request).await.unwrap();
+    let mut buf = [0u8; 1500];
+    let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+        .await
+        .expect("TURN server did not answer")
+        .unwrap();
+    let message_type = u16::from_be_bytes([buf[0], buf[1]]);
+    let mut attributes = Vec::new();
+    let mut at = 20;
+    while at + 4 <= len {
+        let kind = u16::from_be_bytes([buf[at], buf[at + 1]]);
+        let size = u16::from_be_bytes([buf[at + 2], buf[at + 3]]) as usize;
+        attributes.push((kind, buf[at + 4..at + 4 + size].to_vec()));
+        at += 4 + size.next_multiple_of(4);
+    }
+    (message_type & 0x0110, attributes)
+}
+
+fn attribute(attributes: &[(u16, Vec<u8>)], kind: u16) -> Vec<u8> {
+    attributes
+        .iter()
+        .find(|(k, _)| *k == kind)
+        .map(|(_, v)| v.clone())
+        .unwrap_or_else(|| panic!("no attribute {:#06x}", kind))
+}
+
+/// Allocates a relay on a coturn started with `use-auth-secret` and
+/// `static-auth-secret=$TURN_TEST_SECRET`, listening on `$TURN_TEST_SERVER`
+/// (host:port, UDP), using the credentials the hub hands out:
+///
+///     turnserver -n --use-auth-secret --static-auth-secret=north --realm=hub
+///     TURN_TEST_SERVER=127.0.0.1:3478 TURN_TEST_SECRET=north \
+///         cargo test --test turn_test -- --ignored
+#[tokio::test]
+#[ignore = "needs a local coturn"]
+async fn coturn_accepts_issued_credentials() {
+    let server = std::env::var("TURN_TEST_SERVER").unwrap_or("127.0.0.1:3478".to_string());
+    let secret = std::env::var("TURN_TEST_SECRET").unwrap_or("north".to_string());
+    let state = AppState::in_memory().with_ice_config(IceConfig {
+        turn_urls: vec![format!("turn:{}?transport=udp", server)],
+        turn_secret: Some(secret),
+        ttl: Duration::from_secs(600),
+        ..Default::default()
+    });
+    let (_, body) = get_json(&state, "alice", "/ice-servers").await;
+    let turn = &body["ice_servers"][0];
+    let username = turn["username"].as_str().unwrap().as_bytes().to_vec();
+    let password = turn["credential"].as_str().unwrap();
+
+    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    socket.connect(&server).await.unwrap();
+    let udp = (REQUESTED_TRANSPORT, vec![17, 0, 0, 0]);
+
+    // The first attempt is refused with the realm and a nonce to sign with.
+    let (class, challenge) = exchange(
+        &socket,
+        &stun_request(ALLOCATE, std::slice::from_ref(&udp), None),
+    )
+    .await;
+    assert_eq!(class, 0x0110);
+    assert_eq!(&attribute(&challenge, ERROR_CODE)[2..4], &[4, 1]);
+    let realm = attribute(&challenge, REALM);
+    let nonce = attribute(&challenge, NONCE);
+
+    let mut md5 = Md5::new();
+    md5.update(&username);
+    md5.update(b":");
+    md5.update(&realm);
+    md5.update(b":");
+    md5.update(password.as_bytes());
+    let key = md5.finalize();
+
+    let signed = [
+        udp,
+        (USERNAME, username.clone()),
+        (REALM, realm.clone()),
+        (NONCE, nonce.clone()),
+    ];
+    let (class, response) = exchange(&socket, &stun_request(ALLOCATE, &signed, Some(&key))).await;
+    assert_eq!(class, 0x0100, "allocation refused: {:?}", response);
+
+    // Give the relay back straight away.
+    let release = [
+        (LIFETIME, vec![0, 0, 0, 0]),
+        (USERNAME, username),
+        (REALM, realm),
+        (NONCE, nonce),
+    ];
+    let (class, _) = exchange(&socket, &stun_request(REFRESH, &release, Some(&key))).await;
+    assert_eq!(class, 0x0100);
+}